@@ -0,0 +1,417 @@
+use crate::cpu::AddressingMode;
+use crate::cpu::CpuModel;
+use crate::opcodes;
+use crate::opcodes::OpCode;
+use std::collections::HashMap;
+
+/*
+    Small 6502 assembler, to write the programs of the tests in mnemonics instead of bytes.
+    It accepts the syntax of the disassembler: one statement per line, optionally preceded by a label ("loop:") and
+    followed by a comment (after ';'). The unofficial opcodes have a '*' prefix, as in the opcodes module.
+    Operands are numbers ($hex, %binary or decimal) or labels, with an optional offset ("table+1"), and a '<' or '>'
+    prefix selects the low or the high byte. The directives are:
+        .org address    Address of the code that follows, which is padded with zeros up to it
+        .byte values    Bytes
+        .word values    Little-endian words
+*/
+
+const DEFAULT_ORIGIN: u16 = 0x8000; // where cartridge::test::test_rom places the program
+
+/*
+    Syntax of an operand, which selects the addressing mode together with the size of the value.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Syntax {
+    Implied,    // also the accumulator, "A"
+    Immediate,  // #value
+    Direct,     // value: zero page, absolute, branch target or jump target
+    DirectX,    // value,X
+    DirectY,    // value,Y
+    Indirect,   // (value)
+    IndirectX,  // (value,X)
+    IndirectY,  // (value),Y
+    TwoValues   // zero page,target: the bit branches of the 65C02
+}
+
+enum Statement {
+    Instruction(&'static OpCode, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Org(String)
+}
+
+/*
+    Assemble the source for the NES CPU, starting at 0x8000.
+*/
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    assemble_for(source, CpuModel::Ricoh2A03)
+}
+
+/*
+    Assemble the source with the instruction set of the given CPU model.
+    The first pass chooses the opcode of every instruction, which fixes the addresses of the labels. An operand
+    selects a zero page mode only if its value is known at that point, so forward references are always absolute.
+    The second pass encodes the operands.
+*/
+pub fn assemble_for(source: &str, model: CpuModel) -> Result<Vec<u8>, String> {
+    let mut labels = HashMap::new();
+    let mut statements = vec![];
+    let mut addr = DEFAULT_ORIGIN;
+    let mut started = false;
+
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", index + 1, message);
+        let code = match line.find(';') {
+            Some(comment) => &line[..comment],
+            None => line
+        }.trim();
+
+        let code = match code.find(':') {
+            Some(colon) if is_identifier(code[..colon].trim()) => {
+                let label = code[..colon].trim();
+                if labels.insert(label.to_string(), addr).is_some() {
+                    return Err(error(format!("duplicate label {}", label)));
+                }
+                code[colon + 1..].trim()
+            }
+            _ => code
+        };
+        if code.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(code, &labels, model).map_err(error)?;
+        addr = match &statement {
+            Statement::Instruction(opcode, _) => addr.wrapping_add(opcode.len as u16),
+            Statement::Bytes(values) => addr.wrapping_add(values.len() as u16),
+            Statement::Words(values) => addr.wrapping_add(2 * values.len() as u16),
+            Statement::Org(value) => {
+                let origin = evaluate(value, &labels).map_err(error)?;
+                if !(0..=0xFFFF).contains(&origin) || (started && origin < addr as i32) {
+                    return Err(error(format!("invalid origin {}", value)));
+                }
+                origin as u16
+            }
+        };
+        started = started || !matches!(statement, Statement::Org(_));
+        statements.push((index + 1, statement));
+    }
+
+    let mut bytes = vec![];
+    let mut addr = DEFAULT_ORIGIN;
+    let mut started = false;
+    for (line, statement) in statements {
+        let error = |message: String| format!("line {}: {}", line, message);
+        let encoded = match statement {
+            Statement::Instruction(opcode, operands) => encode(opcode, &operands, addr, &labels).map_err(error)?,
+            Statement::Bytes(values) => {
+                let mut encoded = vec![];
+                for value in values {
+                    encoded.push(byte(evaluate(&value, &labels).map_err(error)?).map_err(error)?);
+                }
+                encoded
+            }
+            Statement::Words(values) => {
+                let mut encoded = vec![];
+                for value in values {
+                    encoded.extend(word(evaluate(&value, &labels).map_err(error)?).map_err(error)?.to_le_bytes());
+                }
+                encoded
+            }
+            Statement::Org(value) => {
+                let origin = evaluate(&value, &labels).map_err(error)? as u16;
+                if started {
+                    bytes.resize(bytes.len() + (origin - addr) as usize, 0);
+                }
+                addr = origin;
+                continue;
+            }
+        };
+        addr = addr.wrapping_add(encoded.len() as u16);
+        bytes.extend(encoded);
+        started = true;
+    }
+    Ok(bytes)
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false
+    }
+}
+
+/*
+    Parse a directive or an instruction, choosing its opcode with the labels that are known so far.
+*/
+fn parse_statement(code: &str, labels: &HashMap<String, u16>, model: CpuModel) -> Result<Statement, String> {
+    let (name, operand) = match code.find(char::is_whitespace) {
+        Some(space) => (&code[..space], code[space..].trim()),
+        None => (code, "")
+    };
+    let name = name.to_uppercase();
+    let values = || operand.split(',').map(|value| value.trim().to_string()).collect::<Vec<String>>();
+
+    match name.as_str() {
+        ".BYTE" => Ok(Statement::Bytes(values())),
+        ".WORD" => Ok(Statement::Words(values())),
+        ".ORG" => Ok(Statement::Org(operand.to_string())),
+        _ => {
+            let (syntax, operands) = parse_operand(operand);
+            let candidates = opcodes::instruction_set(model).iter()
+                .filter(|opcode| opcode.mnemonic == name)
+                .collect::<Vec<&OpCode>>();
+            if candidates.is_empty() {
+                return Err(format!("unknown instruction {}", name));
+            }
+
+            let zero_page = match operands.first() {
+                Some(value) => evaluate(value, labels).is_ok_and(|value| (0..=0xFF).contains(&value)),
+                None => false
+            };
+            let candidates = candidates.into_iter()
+                .filter(|opcode| operand_syntax(opcode) == syntax)
+                .collect::<Vec<&OpCode>>();
+            // Prefer the zero page modes for values that fit, and the absolute modes otherwise
+            match candidates.iter().find(|opcode| is_zero_page(opcode) == zero_page).or(candidates.first()) {
+                Some(opcode) => Ok(Statement::Instruction(opcode, operands)),
+                None => Err(format!("invalid operand for {}: {}", name, operand))
+            }
+        }
+    }
+}
+
+fn is_zero_page(opcode: &OpCode) -> bool {
+    matches!(opcode.mode, AddressingMode::ZeroPage | AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y)
+}
+
+/*
+    Syntax of the operand, and the values in it.
+*/
+fn parse_operand(operand: &str) -> (Syntax, Vec<String>) {
+    let text = operand.split_whitespace().collect::<String>();
+    let upper = text.to_uppercase();
+    let len = text.len();
+
+    if text.is_empty() || upper == "A" {
+        (Syntax::Implied, vec![])
+    } else if let Some(value) = text.strip_prefix('#') {
+        (Syntax::Immediate, vec![value.to_string()])
+    } else if text.starts_with('(') && upper.ends_with(",X)") {
+        (Syntax::IndirectX, vec![text[1..len - 3].to_string()])
+    } else if text.starts_with('(') && upper.ends_with("),Y") {
+        (Syntax::IndirectY, vec![text[1..len - 3].to_string()])
+    } else if text.starts_with('(') && text.ends_with(')') {
+        (Syntax::Indirect, vec![text[1..len - 1].to_string()])
+    } else {
+        match text.rfind(',') {
+            Some(comma) => match &upper[comma + 1..] {
+                "X" => (Syntax::DirectX, vec![text[..comma].to_string()]),
+                "Y" => (Syntax::DirectY, vec![text[..comma].to_string()]),
+                _ => (Syntax::TwoValues, vec![text[..comma].to_string(), text[comma + 1..].to_string()])
+            },
+            None => (Syntax::Direct, vec![text])
+        }
+    }
+}
+
+/*
+    Syntax of the operand of the opcode.
+*/
+fn operand_syntax(opcode: &OpCode) -> Syntax {
+    match opcode.mode {
+        AddressingMode::Immediate => Syntax::Immediate,
+        AddressingMode::ZeroPage | AddressingMode::Absolute => Syntax::Direct,
+        AddressingMode::ZeroPage_X | AddressingMode::Absolute_X => Syntax::DirectX,
+        AddressingMode::ZeroPage_Y | AddressingMode::Absolute_Y => Syntax::DirectY,
+        AddressingMode::Indirect_X => Syntax::IndirectX,
+        AddressingMode::Indirect_Y => Syntax::IndirectY,
+        AddressingMode::ZeroPage_Indirect => Syntax::Indirect,
+        AddressingMode::ZeroPage_Relative => Syntax::TwoValues,
+        // Implied instructions, branches, and the jumps, which have the indirect modes of their own
+        AddressingMode::NoneAddressing => match (opcode.len, opcode.code) {
+            (1, _) => Syntax::Implied,
+            (3, 0x6C) => Syntax::Indirect,
+            (3, 0x7C) => Syntax::IndirectX,
+            _ => Syntax::Direct
+        }
+    }
+}
+
+/*
+    Bytes of the instruction at the given address.
+*/
+fn encode(opcode: &OpCode, operands: &[String], addr: u16, labels: &HashMap<String, u16>) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![opcode.code];
+    let values = operands.iter()
+        .map(|operand| evaluate(operand, labels))
+        .collect::<Result<Vec<i32>, String>>()?;
+    let next = addr.wrapping_add(opcode.len as u16);
+
+    match (opcode.mode, opcode.len) {
+        (_, 1) => {}
+        // Branches
+        (AddressingMode::NoneAddressing, 2) => bytes.push(offset(values[0], next)?),
+        (AddressingMode::ZeroPage_Relative, _) => {
+            bytes.push(byte(values[0])?);
+            bytes.push(offset(values[1], next)?);
+        }
+        (_, 2) => bytes.push(byte(values[0])?),
+        _ => bytes.extend(word(values[0])?.to_le_bytes())
+    }
+    Ok(bytes)
+}
+
+fn byte(value: i32) -> Result<u8, String> {
+    if (-0x80..=0xFF).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("value out of range for a byte: {}", value))
+    }
+}
+
+fn word(value: i32) -> Result<u16, String> {
+    if (-0x8000..=0xFFFF).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("value out of range for a word: {}", value))
+    }
+}
+
+/*
+    Offset of a branch to the target, from the instruction that follows it.
+*/
+fn offset(target: i32, next: u16) -> Result<u8, String> {
+    let offset = target - next as i32;
+    if (-0x80..=0x7F).contains(&offset) {
+        Ok(offset as u8)
+    } else {
+        Err(format!("branch target out of range: ${:04X}", target))
+    }
+}
+
+/*
+    Value of an expression: a sum of numbers and labels, optionally preceded by '<' (low byte) or '>' (high byte).
+*/
+fn evaluate(expression: &str, labels: &HashMap<String, u16>) -> Result<i32, String> {
+    let expression = expression.trim();
+    if let Some(rest) = expression.strip_prefix('<') {
+        return Ok(evaluate(rest, labels)? & 0xFF);
+    }
+    if let Some(rest) = expression.strip_prefix('>') {
+        return Ok((evaluate(rest, labels)? >> 8) & 0xFF);
+    }
+    if expression.is_empty() {
+        return Err(String::from("missing value"));
+    }
+
+    let mut total = 0;
+    let mut sign = 1;
+    let mut start = 0;
+    for (index, c) in expression.char_indices().chain(std::iter::once((expression.len(), '+'))) {
+        if c == '+' || c == '-' {
+            let term = expression[start..index].trim();
+            if !term.is_empty() {
+                total += sign * term_value(term, labels)?;
+            }
+            sign = if c == '-' { -1 } else { 1 };
+            start = index + 1;
+        }
+    }
+    Ok(total)
+}
+
+fn term_value(term: &str, labels: &HashMap<String, u16>) -> Result<i32, String> {
+    let number = if let Some(hex) = term.strip_prefix('$') {
+        i32::from_str_radix(hex, 16)
+    } else if let Some(binary) = term.strip_prefix('%') {
+        i32::from_str_radix(binary, 2)
+    } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+        term.parse::<i32>()
+    } else if is_identifier(term) {
+        return labels.get(term).map(|&addr| addr as i32).ok_or(format!("unknown label {}", term));
+    } else {
+        return Err(format!("invalid value {}", term));
+    };
+    number.map_err(|_| format!("invalid number {}", term))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disasm;
+
+    #[test]
+    fn test_assemble_instructions() {
+        assert_eq!(assemble("LDA #$C0\nTAX\nINX\nBRK"), Ok(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]));
+        assert_eq!(
+            assemble("lda $10 ; zero page\n lda $1234,x\n ldx $10,Y\n asl a\n sta ($20),y\n jmp ($02FE)"),
+            Ok(vec![0xa5, 0x10, 0xbd, 0x34, 0x12, 0xb6, 0x10, 0x0a, 0x91, 0x20, 0x6c, 0xfe, 0x02])
+        );
+        // Unofficial opcodes
+        assert_eq!(assemble("*LAX ($40,X)\n*NOP $04\nNOP"), Ok(vec![0xa3, 0x40, 0x04, 0x04, 0xea]));
+        // The 65C02 modes
+        assert_eq!(
+            assemble_for("LDA ($20)\nINC A\nJMP ($1234,X)\nloop: BBS7 $21,loop", CpuModel::Wdc65C02),
+            Ok(vec![0xb2, 0x20, 0x1a, 0x7c, 0x34, 0x12, 0xff, 0x21, 0xfd])
+        );
+    }
+
+    #[test]
+    fn test_labels_and_directives() {
+        let source = "
+            start:  LDX #<table
+                    LDA table+1,X   ; forward reference: absolute
+            loop:   DEX
+                    BNE loop
+                    JSR end
+            table:  .byte 1, %10, $FF, -1
+            end:    .word start, >end
+                    .org $FFFC
+                    .word start
+        ";
+        let bytes = assemble(source).unwrap();
+        assert_eq!(bytes[..0x13], [
+            0xa2, 0x0b, 0xbd, 0x0c, 0x80, 0xca, 0xd0, 0xfd, 0x20, 0x0f, 0x80, 0x01, 0x02, 0xff, 0xff, 0x00, 0x80,
+            0x80, 0x00
+        ]);
+        assert_eq!(bytes.len(), 0x7FFE);
+        assert_eq!(bytes[0x7FFC..], [0x00, 0x80]);
+    }
+
+    #[test]
+    fn test_assembler_errors() {
+        assert_eq!(assemble("NOP\nFOO #1"), Err(String::from("line 2: unknown instruction FOO")));
+        assert_eq!(assemble("STX $10,X"), Err(String::from("line 1: invalid operand for STX: $10,X")));
+        assert_eq!(assemble("LDA #$100"), Err(String::from("line 1: value out of range for a byte: 256")));
+        assert_eq!(assemble("JMP nowhere"), Err(String::from("line 1: unknown label nowhere")));
+        assert_eq!(assemble("a: NOP\na: NOP"), Err(String::from("line 2: duplicate label a")));
+        assert!(assemble(".org $9000\nNOP\n.org $8000").is_err());
+        assert!(assemble("BEQ $9000").is_err());
+    }
+
+    #[test]
+    fn test_round_trip_with_disassembler() {
+        for model in [CpuModel::Nmos6502, CpuModel::Wdc65C02] {
+            for opcode in opcodes::instruction_set(model) {
+                let mut code = vec![opcode.code, 0x34, 0x12];
+                code.truncate(opcode.len as usize);
+                let instruction = disasm::decode(&code, 0x8000, model).unwrap();
+
+                // The listing gives back the same bytes
+                let listing = disasm::listing(std::slice::from_ref(&instruction));
+                assert_eq!(assemble_for(&listing, model), Ok(code.clone()), "{}", listing);
+
+                // The syntax with the unofficial mnemonics gives back the same instruction (the byte may be a
+                // different opcode with the same behaviour)
+                let syntax = format!("{} {}", instruction.mnemonic, instruction.operand_syntax());
+                let bytes = assemble_for(&syntax, model).unwrap();
+                let decoded = disasm::decode(&bytes, 0x8000, model).unwrap();
+                assert_eq!(format!("{} {}", decoded.mnemonic, decoded.operand_syntax()), syntax);
+            }
+        }
+    }
+}
@@ -33,6 +33,9 @@ const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_IO_REGISTERS: u16 = 0x4000;
+const APU_IO_REGISTERS_END: u16 = 0x401F;
+const PRG_ROM_BANK_SIZE: usize = 0x4000;
 
 
 
@@ -53,17 +56,21 @@ impl Bus {
         Read the space [0x8000, 0x10000], which corresponds to the ROM.
         This maps a region of 32 KiB, but some roms only use 16 KiB.
     */
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        self.rom.prg_rom[self.prg_rom_offset(addr)]
+    }
+
+    fn prg_rom_offset(&self, mut addr: u16) -> usize {
         addr -= 0x8000;
         if self.rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
             addr = addr % 0x4000;
         }
-        self.rom.prg_rom[addr as usize]
+        addr as usize
     }
 }
 
 impl Mem for Bus {
-    fn mem_read(&self, address: u16) -> u8 {
+    fn mem_read(&mut self, address: u16) -> u8 {
         match address {
             RAM ..= RAM_MIRRORS_END => {
                 let mirror_down_addr = address & 0b00000111_11111111;
@@ -73,6 +80,7 @@ impl Mem for Bus {
                 let _mirror_down_addr = address & 0b00100000_00000111;
                 todo!("PPU is not supported yet")
             }
+            APU_IO_REGISTERS ..= APU_IO_REGISTERS_END => 0xFF,
             0x8000..=0xFFFF => self.read_prg_rom(address),
             _ => {
                 println!("Ignoring memory read access at {}", address);
@@ -81,6 +89,33 @@ impl Mem for Bus {
         }
     }
 
+    /*
+        The PPU registers are not peeked at, since their reads have side effects. The APU and I/O registers are not
+        emulated yet, and read as 0xFF like in mem_read, which is also how Nintendulator shows them in its logs.
+    */
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            RAM ..= RAM_MIRRORS_END => {
+                let mirror_down_addr = address & 0b00000111_11111111;
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            APU_IO_REGISTERS ..= APU_IO_REGISTERS_END => 0xFF,
+            0x8000..=0xFFFF => self.read_prg_rom(address),
+            _ => 0
+        }
+    }
+
+    /*
+        The PRG-ROM is not switched, so the banks of 16 KiB are always at the same addresses. A single bank is
+        mirrored at 0xC000.
+    */
+    fn prg_bank(&self, address: u16) -> Option<usize> {
+        match address {
+            0x8000..=0xFFFF => Some(self.prg_rom_offset(address) / PRG_ROM_BANK_SIZE),
+            _ => None
+        }
+    }
+
     fn mem_write(&mut self, address: u16, data: u8) {
         match address {
             RAM ..= RAM_MIRRORS_END => {
@@ -1,6 +1,6 @@
 use crate::opcodes;
-use std::collections::HashMap;
 use crate::bus::Bus;
+use std::fmt;
 
 bitflags! {
     /*
@@ -31,17 +31,138 @@ bitflags! {
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xFD;
 
-pub struct CPU {
+/*
+    Interrupts that make the CPU stop the current program and jump to the address stored in a vector:
+        NMI   -> 0xFFFA (non-maskable, requested by an edge in the NMI line)
+        RESET -> 0xFFFC
+        IRQ   -> 0xFFFE (level-triggered, ignored when the interrupt disable flag is set)
+        BRK   -> 0xFFFE (software interrupt, pushes the status with the B flag set)
+*/
+mod interrupt {
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum InterruptType {
+        NMI,
+        RESET,
+        IRQ,
+        BRK,
+    }
+
+    #[derive(PartialEq, Eq)]
+    pub(super) struct Interrupt {
+        pub(super) itype: InterruptType,
+        pub(super) vector_addr: u16,
+        pub(super) b_flag_mask: u8,
+        pub(super) cpu_cycles: u8,
+    }
+
+    pub(super) const NMI: Interrupt = Interrupt {
+        itype: InterruptType::NMI,
+        vector_addr: 0xFFFA,
+        b_flag_mask: 0b0010_0000,
+        cpu_cycles: 7,
+    };
+
+    pub(super) const RESET: Interrupt = Interrupt {
+        itype: InterruptType::RESET,
+        vector_addr: 0xFFFC,
+        b_flag_mask: 0b0010_0000,
+        cpu_cycles: 7,
+    };
+
+    pub(super) const IRQ: Interrupt = Interrupt {
+        itype: InterruptType::IRQ,
+        vector_addr: 0xFFFE,
+        b_flag_mask: 0b0010_0000,
+        cpu_cycles: 7,
+    };
+
+    pub(super) const BRK: Interrupt = Interrupt {
+        itype: InterruptType::BRK,
+        vector_addr: 0xFFFE,
+        b_flag_mask: 0b0011_0000,
+        cpu_cycles: 7,
+    };
+}
+
+pub use interrupt::InterruptType;
+
+/*
+    Errors that stop the execution of the CPU.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    // The CPU fetched one of the KIL/JAM opcodes (or STP, on the 65C02) and locked up. It stays like this until
+    // it is reset.
+    Jammed { opcode: u8, pc: u16 },
+    // An instruction tried to get the operand address with a mode that does not have one.
+    InvalidAddressingMode { mode: AddressingMode, pc: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::Jammed { opcode, pc } => {
+                write!(f, "CPU jammed by opcode {:02X} at {:04X}", opcode, pc)
+            }
+            CpuError::InvalidAddressingMode { mode, pc } => {
+                write!(f, "Addressing mode {:?} is not supported (instruction at {:04X})", mode, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+/*
+    Outcome of executing a single instruction with CPU::step.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    pub opcode: u8,
+    pub pc_before: u16, // address of the instruction (after servicing the interrupt, if any)
+    pub pc_after: u16,
+    pub cycles: u64, // cycles consumed, including the interrupt sequence
+    pub interrupt: Option<InterruptType>, // interrupt serviced before the instruction
+    pub halted: bool // the halt opcode was fetched, and the instruction was not executed
+}
+
+/*
+    Members of the 6502 family that the CPU can emulate. They differ in the instruction set and in some details
+    of the execution, like decimal mode.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuModel {
+    Ricoh2A03, // CPU of the NES: an NMOS 6502 without decimal mode
+    Nmos6502,  // original MOS 6502, with the unofficial opcodes
+    Wdc65C02,  // CMOS version by WDC, with new instructions and the bugs of the NMOS version fixed
+}
+
+pub struct CPU<M: Mem = Bus> {
     pub register_a: u8, // accumulator
     pub register_x: u8,
     pub register_y: u8,
     pub status: CpuFlags,
     pub program_counter: u16,
     pub stack_pointer: u8,
-    pub bus: Bus
+    pub cycles: u64, // total CPU cycles elapsed
+    pub halt_opcode: Option<u8>, // opcode that stops the execution when fetched, instead of being executed
+    pub decimal_mode: bool, // ADC and SBC use BCD arithmetic when the D flag is set (the NES 2A03 does not support it)
+    pub magic_constant: u8, // chip-dependent value ORed with A by the unstable opcodes XAA and LXA
+    pub cycle_stepped: bool, // each bus access takes one cycle and ticks the bus, instead of counting whole instructions
+    pub bus: M, // memory seen by the CPU: the NES bus, or any other implementation of Mem
+    model: CpuModel,
+    current_opcode: u8, // opcode of the instruction being executed
+    page_crossed: bool, // the indexed operand address of the current instruction is on a different page than the base
+    nmi_pending: bool, // an edge was detected in the NMI line and has not been serviced yet
+    irq_line: bool,    // state of the IRQ line (true if some device is requesting an interrupt)
+    nmi_line: bool,    // last state of the NMI line of the bus, to detect the edges
+    nmi_polled: bool,  // an NMI was pending when the interrupts were last polled
+    irq_polled: bool,  // the IRQ line of the bus was asserted when the interrupts were last polled
+    delayed_interrupt_disable: Option<bool>, // value of the I flag seen by the last poll, if the instruction changed it afterwards
+    jammed: bool       // a KIL/JAM opcode was executed, and the CPU is stuck until reset
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
    Immediate,
@@ -53,19 +174,27 @@ pub enum AddressingMode {
    Absolute_Y,
    Indirect_X,
    Indirect_Y,
+   ZeroPage_Indirect, // 65C02 only
+   ZeroPage_Relative, // 65C02 only: zero page operand followed by a branch offset (BBR and BBS)
    NoneAddressing,
 }
 
 
 pub trait Mem {
-    fn mem_read(&self, address: u16) -> u8;
+    /*
+        Read a value, as the CPU does. Reading some device registers has side effects (like clearing a status flag
+        or advancing a read buffer), so it takes the memory mutably. By default, it is the same as peek.
+    */
+    fn mem_read(&mut self, address: u16) -> u8 {
+        self.peek(address)
+    }
 
     fn mem_write(&mut self, address: u16, data: u8);
 
-    fn mem_read_u16(&self, address: u16) -> u16 {
+    fn mem_read_u16(&mut self, address: u16) -> u16 {
         // Read a 2-byte value, stored in little-endian convention
         let lo = self.mem_read(address) as u16;
-        let hi = self.mem_read(address + 1) as u16;
+        let hi = self.mem_read(address.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
 
@@ -73,16 +202,109 @@ pub trait Mem {
         let hi = (data >> 8) as u8;
         let lo = (data & 0x00ff) as u8;
         self.mem_write(address, lo);
-        self.mem_write(address + 1, hi);
+        self.mem_write(address.wrapping_add(1), hi);
+    }
+
+    /*
+        Read a value without the side effects that a read by the CPU may have on a device. It is meant for the
+        trace, the disassembler and debuggers. Memory whose reads have no side effects only needs to implement this.
+    */
+    fn peek(&self, address: u16) -> u8;
+
+    fn peek_u16(&self, address: u16) -> u16 {
+        let lo = self.peek(address) as u16;
+        let hi = self.peek(address.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    /*
+        Bank of PRG-ROM mapped at the address, for the trace logger and other debugging tools. None if the address is
+        not in PRG-ROM, or if the memory has no banks.
+    */
+    fn prg_bank(&self, _address: u16) -> Option<usize> {
+        None
+    }
+
+    /*
+        Scanline and dot of the PPU, for the trace. None if there is no PPU on the bus, in which case the trace
+        estimates them from the CPU cycles.
+    */
+    fn ppu_position(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    /*
+        Advance the devices connected to the bus by one CPU cycle. When the CPU is cycle-stepped, it is called
+        before every read and write, so the devices can be kept in sync with each individual access.
+    */
+    fn tick(&mut self) {}
+
+    /*
+        Interrupt lines driven by the devices on the bus (true when asserted). The CPU samples them at the beginning
+        of every cycle, so with a cycle-stepped CPU the timing of each change is taken into account. NMI is
+        triggered by the edge, while IRQ is serviced for as long as it is asserted and enabled.
+    */
+    fn nmi_line(&self) -> bool {
+        false
+    }
+
+    fn irq_line(&self) -> bool {
+        false
     }
 }
 
-impl Mem for CPU {
-    fn mem_read(&self, address: u16) -> u8 {
+/*
+    Check whether two addresses are in different pages (256-byte blocks of memory).
+*/
+fn page_crossed(address1: u16, address2: u16) -> bool {
+    address1 & 0xFF00 != address2 & 0xFF00
+}
+
+/*
+    Address that the processor reads while indexing, before carrying to the high byte: the high byte of the base and
+    the low byte of the result.
+*/
+fn uncarried(base: u16, indexed: u16) -> u16 {
+    (base & 0xFF00) | (indexed & 0x00FF)
+}
+
+/*
+    Dummy reads done while computing an indexed address, which are visible on the bus.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DummyReads {
+    OnPageCross, // read instructions only repeat the read when the indexing crosses a page
+    Always,      // stores and read-modify-write instructions always read before writing
+}
+
+type OpHandler<M> = fn(&mut CPU<M>, &AddressingMode) -> Result<(), CpuError>;
+
+/*
+    Build a dispatch table of the CPU from the definition of an instruction set in the opcodes module: for each
+    opcode, a pointer to the method that executes it.
+*/
+macro_rules! opcode_handlers {
+    ($((
+        $code:expr, $mnemonic:expr, $len:expr, $cycles:expr, $mode:ident, $handler:ident, $official:ident,
+        $penalty:ident
+    )),* $(,)?) => {{
+        let undefined: OpHandler<M> = |_, _| unreachable!("Every opcode is defined in the instruction set");
+        let mut table = [undefined; 256];
+        $(
+            table[$code] = |cpu: &mut CPU<M>, mode: &AddressingMode| {
+                cpu.$handler(mode).map(|_| ())
+            };
+        )*
+        table
+    }};
+}
+
+impl<M: Mem> Mem for CPU<M> {
+    fn mem_read(&mut self, address: u16) -> u8 {
         self.bus.mem_read(address)
     }
 
-    fn mem_read_u16(&self, address: u16) -> u16 {
+    fn mem_read_u16(&mut self, address: u16) -> u16 {
         self.bus.mem_read_u16(address)
     }
 
@@ -93,10 +315,35 @@ impl Mem for CPU {
     fn mem_write_u16(&mut self, address: u16, data: u16) {
         self.bus.mem_write_u16(address, data);
     }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.bus.peek(address)
+    }
+
+    fn prg_bank(&self, address: u16) -> Option<usize> {
+        self.bus.prg_bank(address)
+    }
+
+    fn ppu_position(&self) -> Option<(u16, u16)> {
+        self.bus.ppu_position()
+    }
 }
 
-impl CPU {
-    pub fn new(bus: Bus) -> Self {
+impl<M: Mem> CPU<M> {
+    const OPCODE_HANDLERS: [OpHandler<M>; 256] = opcodes::cpu_opcodes!(opcode_handlers);
+    const OPCODE_HANDLERS_65C02: [OpHandler<M>; 256] = opcodes::cpu_opcodes_65c02!(opcode_handlers);
+
+    /*
+        Create the CPU of the NES.
+    */
+    pub fn new(bus: M) -> Self {
+        CPU::with_model(bus, CpuModel::Ricoh2A03)
+    }
+
+    /*
+        Create a CPU of the given model. Decimal mode is enabled in every model but the 2A03.
+    */
+    pub fn with_model(bus: M, model: CpuModel) -> Self {
         CPU {
             register_a: 0,
             register_x: 0,
@@ -104,69 +351,259 @@ impl CPU {
             status: CpuFlags::from_bits_truncate(0b100100),
             program_counter: 0x8000,
             stack_pointer: STACK_RESET,
-            bus: bus
+            cycles: 0,
+            halt_opcode: None,
+            decimal_mode: model != CpuModel::Ricoh2A03,
+            magic_constant: 0xEE,
+            cycle_stepped: false,
+            bus,
+            model,
+            current_opcode: 0,
+            page_crossed: false,
+            nmi_pending: false,
+            irq_line: false,
+            nmi_line: false,
+            nmi_polled: false,
+            irq_polled: false,
+            delayed_interrupt_disable: None,
+            jammed: false
         }
     }
 
-    pub fn get_absolute_address(&self, mode: &AddressingMode, address: u16) -> u16 {
-        match mode {
-            AddressingMode::Immediate => address,
-            AddressingMode::ZeroPage => self.mem_read(address) as u16,
-            AddressingMode::Absolute => self.mem_read_u16(address),
+    /*
+        Compute the address of the operand stored at the given address, for the given addressing mode.
+        The second value returned is true if the indexing crossed a page boundary, which costs an extra cycle
+        to the instructions that read from memory.
+        It only computes the address, peeking at memory without the cycles and dummy reads of the CPU, so it can be
+        used by the trace and debuggers.
+    */
+    pub fn get_absolute_address(&self, mode: &AddressingMode, address: u16) -> Result<(u16, bool), CpuError> {
+        let result = match mode {
+            AddressingMode::Immediate => (address, false),
+            AddressingMode::ZeroPage => (self.peek(address) as u16, false),
+            AddressingMode::Absolute => (self.peek_u16(address), false),
+
+            AddressingMode::ZeroPage_X => {
+                let pos = self.peek(address);
+                (pos.wrapping_add(self.register_x) as u16, false)
+            },
+            AddressingMode::ZeroPage_Y => {
+                let pos = self.peek(address);
+                (pos.wrapping_add(self.register_y) as u16, false)
+            },
+            AddressingMode::Absolute_X => {
+                let base = self.peek_u16(address);
+                let output_address = base.wrapping_add(self.register_x as u16);
+                (output_address, page_crossed(base, output_address))
+            },
+            AddressingMode::Absolute_Y => {
+                let base = self.peek_u16(address);
+                let output_address = base.wrapping_add(self.register_y as u16);
+                (output_address, page_crossed(base, output_address))
+            },
+            AddressingMode::Indirect_X => {
+                let base = self.peek(address);
+
+                let ptr: u8 = base.wrapping_add(self.register_x);
+                let lo = self.peek(ptr as u16);
+                let hi = self.peek(ptr.wrapping_add(1) as u16);
+                ((hi as u16) << 8 | (lo as u16), false)
+            },
+            AddressingMode::Indirect_Y => {
+                let base = self.peek(address);
+
+                let lo = self.peek(base as u16);
+                let hi = self.peek(base.wrapping_add(1) as u16);
+                let deref_base = (hi as u16) << 8 | (lo as u16);
+                let deref = deref_base.wrapping_add(self.register_y as u16);
+                (deref, page_crossed(deref_base, deref))
+            },
+            AddressingMode::ZeroPage_Indirect => {
+                let base = self.peek(address);
+
+                let lo = self.peek(base as u16);
+                let hi = self.peek(base.wrapping_add(1) as u16);
+                ((hi as u16) << 8 | (lo as u16), false)
+            },
+            // The branch offset that follows is read by the instruction itself
+            AddressingMode::ZeroPage_Relative => (self.peek(address) as u16, false),
+            AddressingMode::NoneAddressing => {
+                // The operand comes right after the opcode
+                return Err(CpuError::InvalidAddressingMode { mode: *mode, pc: address.wrapping_sub(1) });
+            }
+        };
+        Ok(result)
+    }
+
+    /*
+        Same as get_absolute_address, but going through the bus like the processor does: one access per cycle,
+        including the dummy reads done while indexing.
+    */
+    fn resolve_address(&mut self, mode: &AddressingMode, address: u16, dummy_reads: DummyReads) -> Result<(u16, bool), CpuError> {
+        let result = match mode {
+            AddressingMode::Immediate => (address, false),
+            AddressingMode::ZeroPage => (self.read(address) as u16, false),
+            AddressingMode::Absolute => (self.read_u16(address), false),
 
             AddressingMode::ZeroPage_X => {
-                let pos = self.mem_read(address);
+                let pos = self.read(address);
+                self.indexing_dummy_read(dummy_reads, true, pos as u16, address);
                 let output_address = pos.wrapping_add(self.register_x) as u16;
-                output_address
+                (output_address, false)
             },
             AddressingMode::ZeroPage_Y => {
-                let pos = self.mem_read(address);
+                let pos = self.read(address);
+                self.indexing_dummy_read(dummy_reads, true, pos as u16, address);
                 let output_address = pos.wrapping_add(self.register_y) as u16;
-                output_address
+                (output_address, false)
             },
             AddressingMode::Absolute_X => {
-                let base = self.mem_read_u16(address);
+                let base = self.read_u16(address);
                 let output_address = base.wrapping_add(self.register_x as u16);
-                output_address
+                let page_cross = page_crossed(base, output_address);
+                self.indexing_dummy_read(dummy_reads, page_cross, uncarried(base, output_address), address.wrapping_add(1));
+                (output_address, page_cross)
             },
             AddressingMode::Absolute_Y => {
-                let base = self.mem_read_u16(address);
+                let base = self.read_u16(address);
                 let output_address = base.wrapping_add(self.register_y as u16);
-                output_address
+                let page_cross = page_crossed(base, output_address);
+                self.indexing_dummy_read(dummy_reads, page_cross, uncarried(base, output_address), address.wrapping_add(1));
+                (output_address, page_cross)
             },
             AddressingMode::Indirect_X => {
-                let base = self.mem_read(address);
- 
+                let base = self.read(address);
+                self.indexing_dummy_read(dummy_reads, true, base as u16, address);
+
                 let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16)
+                let lo = self.read(ptr as u16);
+                let hi = self.read(ptr.wrapping_add(1) as u16);
+                ((hi as u16) << 8 | (lo as u16), false)
             },
             AddressingMode::Indirect_Y => {
-                let base = self.mem_read(address);
+                let base = self.read(address);
  
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
+                let lo = self.read(base as u16);
+                let hi = self.read(base.wrapping_add(1) as u16);
                 let deref_base = (hi as u16) << 8 | (lo as u16);
                 let deref = deref_base.wrapping_add(self.register_y as u16);
-                deref
+                let page_cross = page_crossed(deref_base, deref);
+                self.indexing_dummy_read(dummy_reads, page_cross, uncarried(deref_base, deref), address);
+                (deref, page_cross)
             },
+            AddressingMode::ZeroPage_Indirect => {
+                let base = self.read(address);
+
+                let lo = self.read(base as u16);
+                let hi = self.read(base.wrapping_add(1) as u16);
+                ((hi as u16) << 8 | (lo as u16), false)
+            },
+            AddressingMode::ZeroPage_Relative => (self.read(address) as u16, false),
             AddressingMode::NoneAddressing => {
-                panic!("Addressing mode {:?} is not supported.", mode)
+                return Err(CpuError::InvalidAddressingMode { mode: *mode, pc: address.wrapping_sub(1) });
+            }
+        };
+        Ok(result)
+    }
+
+    /*
+        Dummy read done while indexing, at the address computed before carrying to the high byte (or, for the
+        indexed zero page modes, at the base address). The 65C02 reads the last byte of the instruction instead.
+        Read instructions skip it when the indexing does not cross a page.
+    */
+    fn indexing_dummy_read(&mut self, dummy_reads: DummyReads, page_cross: bool, uncarried_address: u16, operand_end: u16) {
+        let needed = match dummy_reads {
+            DummyReads::OnPageCross => page_cross,
+            DummyReads::Always => true
+        };
+        if needed {
+            if self.model == CpuModel::Wdc65C02 {
+                self.read(operand_end);
+            } else {
+                self.read(uncarried_address);
             }
         }
     }
 
     /*
-        Get the address of the next operand, depending on the addressing mode
+        Get the address of the next operand, and whether the indexing crossed a page. The instructions with a page
+        cross penalty in the opcode table only do the dummy read of indexed modes when the page is crossed, and the
+        extra cycle is counted after running the instruction. The others (stores and most read-modify-write
+        instructions) always do it.
+    */
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> Result<(u16, bool), CpuError> {
+        let dummy_reads = if opcodes::table(self.model)[self.current_opcode as usize].page_cross_penalty {
+            DummyReads::OnPageCross
+        } else {
+            DummyReads::Always
+        };
+        let (address, page_cross) = self.resolve_address(mode, self.program_counter, dummy_reads)?;
+        self.page_crossed = page_cross;
+        Ok((address, page_cross))
+    }
+
+    /*
+        Read from the bus, as part of the execution of an instruction. Each access takes one cycle.
+    */
+    fn read(&mut self, address: u16) -> u8 {
+        self.tick();
+        self.bus.mem_read(address)
+    }
+
+    fn read_u16(&mut self, address: u16) -> u16 {
+        let lo = self.read(address) as u16;
+        let hi = self.read(address.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    /*
+        Write to the bus, as part of the execution of an instruction. Each access takes one cycle.
+    */
+    fn write(&mut self, address: u16, data: u8) {
+        self.tick();
+        self.bus.mem_write(address, data);
+    }
+
+    /*
+        Start a new cycle, polling the interrupt lines. If the CPU is cycle-stepped, the cycle is also counted and
+        the bus is ticked. Otherwise, the cycles are counted per instruction.
+    */
+    fn tick(&mut self) {
+        self.poll_interrupts();
+        if self.cycle_stepped {
+            self.cycles += 1;
+            self.bus.tick();
+        }
+    }
+
+    /*
+        Sample the interrupt lines of the bus, as they were at the end of the previous cycle. The poll done before
+        the last cycle of an instruction (so, the one that sees the second to last cycle) is the one that decides
+        whether an interrupt is serviced after the instruction.
+    */
+    fn poll_interrupts(&mut self) {
+        let nmi_line = self.bus.nmi_line();
+        if nmi_line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = nmi_line;
+        self.nmi_polled = self.nmi_pending;
+        self.irq_polled = self.bus.irq_line();
+    }
+
+    /*
+        Account for cycles of an instruction or an interrupt sequence, when the CPU is not cycle-stepped. In
+        cycle-stepped mode, the same cycles are counted by the bus accesses that the CPU does during them.
     */
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
-        self.get_absolute_address(mode, self.program_counter)
+    fn add_cycles(&mut self, cycles: u64) {
+        if !self.cycle_stepped {
+            self.cycles += cycles;
+        }
     }
 
     fn stack_pop(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
-        self.mem_read((STACK as u16) + self.stack_pointer as u16)
+        self.read(STACK + self.stack_pointer as u16)
     }
 
     fn stack_pop_u16(&mut self) -> u16 {
@@ -176,8 +613,13 @@ impl CPU {
         hi << 8 | lo
     }
 
+    // The instructions that pull from the stack read the current top before incrementing the stack pointer.
+    fn stack_dummy_read(&mut self) {
+        self.read(STACK + self.stack_pointer as u16);
+    }
+
     fn stack_push(&mut self, data: u8) {
-        self.mem_write((STACK as u16) + self.stack_pointer as u16, data);
+        self.write(STACK + self.stack_pointer as u16, data);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
@@ -188,15 +630,28 @@ impl CPU {
         self.stack_push(lo);
     }
 
+    /*
+        Write the result of a read-modify-write instruction. The NMOS 6502 writes the unmodified value back while it
+        computes the result, so the address is written twice. The 65C02 reads it again instead.
+    */
+    fn write_modified(&mut self, address: u16, original: u8, result: u8) {
+        if self.model == CpuModel::Wdc65C02 {
+            self.read(address);
+        } else {
+            self.write(address, original);
+        }
+        self.write(address, result);
+    }
+
     fn set_register_a(&mut self, value: u8) {
         self.register_a = value;
         self.update_zero_and_negative_flags(self.register_a);
     }
     
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<(), CpuError> {
         self.load(program);
         self.reset();
-        self.run();
+        self.run()
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
@@ -223,604 +678,347 @@ impl CPU {
         self.register_y = 0;
         self.stack_pointer = STACK_RESET;
         self.status = CpuFlags::from_bits_truncate(0b100100);
+        self.nmi_pending = false;
+        self.irq_line = false;
+        self.delayed_interrupt_disable = None;
+        self.jammed = false;
+
+        // The reset sequence goes through the same 7 cycles as an interrupt, but the writes to the stack are turned
+        // into reads. The program counter is loaded from the reset vector.
+        self.cycles = 0;
+        self.read(self.program_counter);
+        self.read(self.program_counter);
+        for offset in 0..3 {
+            self.read(STACK + STACK_RESET.wrapping_add(3 - offset) as u16);
+        }
+        self.program_counter = self.read_u16(interrupt::RESET.vector_addr);
+        self.add_cycles(interrupt::RESET.cpu_cycles as u64);
+        self.nmi_polled = false;
+        self.irq_polled = false;
+    }
 
-        self.program_counter = self.mem_read_u16(0xFFFC);
+    pub fn model(&self) -> CpuModel {
+        self.model
     }
 
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
+    /*
+        True if the CPU executed a KIL/JAM opcode. Only a reset gets it running again.
+    */
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
-    where 
-        F: FnMut(&mut CPU)
-    {
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
+    /*
+        Signal an edge in the NMI line. The interrupt is serviced before the next instruction, regardless of the
+        interrupt disable flag: the edge is taken as seen by the poll of the instruction that has just finished.
+        Devices that need the exact timing of the edge drive Mem::nmi_line instead.
+    */
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+        self.nmi_polled = true;
+    }
 
-        loop {
-            callback(self);
+    /*
+        Set the state of the IRQ line. While it is asserted and the interrupt disable flag is clear, an interrupt is
+        serviced before each instruction, so the device has to release the line once it has been acknowledged.
+        Like with trigger_nmi, the line is taken as seen by the poll of the instruction that has just finished.
+    */
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
 
-            let code: u8 = self.mem_read(self.program_counter);
-            self.program_counter += 1;
+    /*
+        Service the interrupt with the highest priority, if the last poll found one. Returns the type of the
+        interrupt that was serviced.
+    */
+    fn service_interrupt(&mut self) -> Option<InterruptType> {
+        // CLI, SEI and PLP change the I flag after the poll, so the change only affects the next instruction.
+        // RTI restores it before, so it takes effect immediately.
+        let interrupt_disable = match self.delayed_interrupt_disable.take() {
+            Some(interrupt_disable) => interrupt_disable,
+            None => self.status.contains(CpuFlags::INTERRUPT_DISABLE)
+        };
+        let interrupt = if self.nmi_polled {
+            interrupt::NMI
+        } else if (self.irq_line || self.irq_polled) && !interrupt_disable {
+            interrupt::IRQ
+        } else {
+            return None;
+        };
 
-            let program_counter_state = self.program_counter;
+        // The opcode at the program counter is fetched and discarded twice, before pushing the return address
+        self.read(self.program_counter);
+        self.read(self.program_counter);
+        self.add_cycles(interrupt.cpu_cycles as u64);
+        Some(self.interrupt(interrupt))
+    }
 
-            let opcode = opcodes.get(&code).expect(&format!("OpCode {:x} is not recognized", code));
+    /*
+        Push the program counter and the status to the stack, and jump to the address in the interrupt vector.
+        The B flag is only set in the pushed copy of the status when the interrupt comes from the BRK instruction.
+        The cycles are accounted by the caller, since for BRK they are part of the instruction.
+        Returns the type of the interrupt whose vector was used.
+    */
+    fn interrupt(&mut self, interrupt: interrupt::Interrupt) -> InterruptType {
+        self.stack_push_u16(self.program_counter);
+        let mut flags = self.status;
+        flags.remove(CpuFlags::BREAK);
+        flags.insert(CpuFlags::BREAK2);
+        flags.bits |= interrupt.b_flag_mask;
+        self.stack_push(flags.bits);
 
-            match code {
-                /* Arithmetic */
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        // The 65C02 also leaves decimal mode, so that the handler does not depend on the interrupted program
+        if self.model == CpuModel::Wdc65C02 {
+            self.status.remove(CpuFlags::DECIMAL_MODE);
+        }
 
-                // ADC
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&opcode.mode);
-                }
+        // An NMI detected before the vector is fetched hijacks BRK and IRQ: the sequence continues with the NMI
+        // vector, and the NMI is not serviced again. The 65C02 fixed it for BRK.
+        let hijacked = self.nmi_pending
+            && (interrupt.itype == InterruptType::IRQ
+                || interrupt.itype == InterruptType::BRK && self.model != CpuModel::Wdc65C02);
+        let (itype, vector_addr) = if hijacked {
+            (InterruptType::NMI, interrupt::NMI.vector_addr)
+        } else {
+            (interrupt.itype, interrupt.vector_addr)
+        };
+        if itype == InterruptType::NMI {
+            self.nmi_pending = false;
+        }
+        self.program_counter = self.read_u16(vector_addr);
 
-                // SBC 
-                0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
-                    self.sbc(&opcode.mode);
-                }
+        // Interrupts are not polled during the sequence, so the first instruction of the handler always runs
+        self.nmi_polled = false;
+        self.irq_polled = false;
+        itype
+    }
 
-                // AND
-                0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-                    self.and(&opcode.mode);
-                }
+    /*
+        Run the program until the halt opcode is fetched, if it has been set. Otherwise, run forever.
+    */
+    pub fn run(&mut self) -> Result<(), CpuError> {
+        self.run_with_callback(|_| {})
+    }
 
-                // EOR
-                0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
-                    self.eor(&opcode.mode);
-                }
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<(), CpuError>
+    where 
+        F: FnMut(&mut CPU<M>)
+    {
+        loop {
+            if self.step_with_callback(&mut callback)?.halted {
+                return Ok(());
+            }
+        }
+    }
 
-                // ORA
-                0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
-                    self.ora(&opcode.mode);
-                }
+    /*
+        Run until the predicate returns true after executing an instruction, or until the CPU halts.
+        Returns the result of the last step.
+    */
+    pub fn run_until<P>(&mut self, mut predicate: P) -> Result<StepResult, CpuError>
+    where
+        P: FnMut(&CPU<M>) -> bool
+    {
+        loop {
+            let result = self.step()?;
+            if result.halted || predicate(self) {
+                return Ok(result);
+            }
+        }
+    }
 
-                /* Shifts */
+    /*
+        Run whole instructions until at least the given number of cycles have elapsed, or until the CPU halts.
+        Returns the result of the last step.
+    */
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<StepResult, CpuError> {
+        let target = self.cycles + cycles;
+        self.run_until(|cpu| cpu.cycles >= target)
+    }
 
-                // ASL
-                0x0a => self.asl_accumulator(),
-                0x06 | 0x16 | 0x0e | 0x1e => {
-                    self.asl(&opcode.mode);
-                }
+    /*
+        Service a pending interrupt, if any, and execute exactly one instruction.
+    */
+    pub fn step(&mut self) -> Result<StepResult, CpuError> {
+        self.step_with_callback(|_| {})
+    }
 
-                // LSR
-                0x4a => self.lsr_accumulator(),
-                0x46 | 0x56 | 0x4e | 0x5e => {
-                    self.lsr(&opcode.mode);
-                }
+    /*
+        Same as step, but calling the callback after servicing the interrupts and before running the instruction.
+    */
+    pub fn step_with_callback<F>(&mut self, mut callback: F) -> Result<StepResult, CpuError>
+    where
+        F: FnMut(&mut CPU<M>)
+    {
+        // A jammed CPU does not respond to interrupts, only to a reset
+        if self.jammed {
+            return Err(CpuError::Jammed {
+                opcode: self.peek(self.program_counter),
+                pc: self.program_counter
+            });
+        }
 
-                // ROL
-                0x2a => self.rol_accumulator(),
-                0x26 | 0x36 | 0x2e | 0x3e => {
-                    self.rol(&opcode.mode);
-                }
+        let cycles_before = self.cycles;
+        let interrupt = self.service_interrupt();
+
+        callback(self);
+
+        let pc_before = self.program_counter;
+        // The halt opcode is checked without accessing the bus, since it is not really executed
+        if let Some(halt_opcode) = self.halt_opcode {
+            if self.peek(pc_before) == halt_opcode {
+                return Ok(StepResult {
+                    opcode: halt_opcode,
+                    pc_before,
+                    pc_after: pc_before,
+                    cycles: self.cycles - cycles_before,
+                    interrupt,
+                    halted: true
+                });
+            }
+        }
+        let code: u8 = self.read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        let program_counter_state = self.program_counter;
+
+        let (opcode, handler) = match self.model {
+            CpuModel::Wdc65C02 => (&opcodes::OPCODES_TABLE_65C02[code as usize], Self::OPCODE_HANDLERS_65C02[code as usize]),
+            _ => (&opcodes::OPCODES_TABLE[code as usize], Self::OPCODE_HANDLERS[code as usize])
+        };
+        self.current_opcode = code;
+        self.page_crossed = false;
+
+        // Single byte instructions read the next byte while they are decoded, and ignore it. Only the 1-cycle NOPs
+        // of the 65C02 skip it.
+        if opcode.len == 1 && opcode.cycles > 1 {
+            self.read(self.program_counter);
+        }
+        handler(self, &opcode.mode)?;
 
-                // ROR
-                0x6a => self.ror_accumulator(),
-                0x66 | 0x76 | 0x6e | 0x7e => {
-                    self.ror(&opcode.mode);
-                }
+        // Move the program counter, if it has not been modified by the current instruction.
+        if program_counter_state == self.program_counter {
+            self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
+        }
 
-                // INC
-                0xe6 | 0xf6 | 0xee | 0xfe => {
-                    self.inc(&opcode.mode);
-                }
+        // Base cycles of the instruction, and the penalty of the instructions that wait for the carry of the high
+        // byte of an indexed address. Branches add their own penalties.
+        self.add_cycles(opcode.cycles as u64);
+        if opcode.page_cross_penalty && self.page_crossed {
+            self.add_cycles(1);
+        }
 
-                // INX
-                0xE8 => self.inx(),
+        Ok(StepResult {
+            opcode: code,
+            pc_before,
+            pc_after: self.program_counter,
+            cycles: self.cycles - cycles_before,
+            interrupt,
+            halted: false
+        })
+    }
 
-                // INY
-                0xC8 => self.iny(),
+    fn update_zero_and_negative_flags(&mut self, result: u8) {
+        // Set the CPU flag corresponding to value equal to zero
+        if result == 0 {
+            self.status.insert(CpuFlags::ZERO);
+        } else {
+            self.status.remove(CpuFlags::ZERO);
+        }
 
-                // DEC
-                0xc6 | 0xd6 | 0xce | 0xde => {
-                    self.dec(&opcode.mode);
-                }
+        // Set the CPU flag corresponding to negative value
+        if (result & 0b1000_0000) != 0 {
+            self.status.insert(CpuFlags::NEGATIVE);
+        } else {
+            self.status.remove(CpuFlags::NEGATIVE);
+        }
+    }
 
-                // DEX
-                0xCA => {
-                    self.dex();
-                }
+    /* Arithmetic */
 
-                // DEY
-                0x88 => {
-                    self.dey();
-                }
+    // True if ADC and SBC have to operate in BCD.
+    fn decimal_arithmetic(&self) -> bool {
+        self.decimal_mode && self.status.contains(CpuFlags::DECIMAL_MODE)
+    }
 
-                // CMP
-                0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-                    self.compare(&opcode.mode, self.register_a);
-                }
+    // Add a value to the register A, taking into account the carry and overflow flags.
+    // http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
+    fn add_to_register_a(&mut self, data: u8) {
+        if self.decimal_arithmetic() {
+            self.add_decimal_to_register_a(data);
+        } else {
+            self.add_binary_to_register_a(data);
+        }
+    }
 
-                // CPY
-                0xc0 | 0xc4 | 0xcc => {
-                    self.compare(&opcode.mode, self.register_y);
-                }
+    // Binary addition. This is the only mode of the NES processor, which does not have decimal mode.
+    fn add_binary_to_register_a(&mut self, data: u8) {
+        let sum = self.register_a as u16
+                + data as u16
+                + (if self.status.contains(CpuFlags::CARRY) {
+                    1
+                } else {
+                    0
+                }) as u16;
 
-                // CPX
-                0xe0 | 0xe4 | 0xec => {
-                    self.compare(&opcode.mode, self.register_x);
-                }
+        // Set carry flag if needed
+        if sum > 0xff {
+            self.status.insert(CpuFlags::CARRY);
+        } else {
+            self.status.remove(CpuFlags::CARRY);
+        }
 
-                /* Branching */
+        // Set overflow flag if needed
+        let result = sum as u8;
+        if (data ^ result) & (result ^ self.register_a) & 0x80 != 0 {
+            self.status.insert(CpuFlags::OVERFLOW);
+        } else {
+            self.status.remove(CpuFlags::OVERFLOW);
+        }
 
-                // JMP absolute
-                0x4c => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = mem_address;
-                }
+        self.set_register_a(result);
+    }
 
-                // JMP indirect
-                0x6c => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
-
-                    // Manage the case in which we are reading the last byte of a page, as explained in 
-                    //      http://www.6502.org/tutorials/6502opcodes.html#JMP
-                    let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-                        let lo = self.mem_read(mem_address);
-                        let hi = self.mem_read(mem_address & 0xFF00);
-                        (hi as u16) << 8 | (lo as u16)
-                    } else {
-                        self.mem_read_u16(mem_address)
-                    };
+    // BCD addition, as done by the NMOS 6502: http://www.6502.org/tutorials/decimal_mode.html#A
+    // The Z flag is set from the binary sum, while N and V are computed after adjusting the low digit, but before
+    // adjusting the high one. Only the carry is valid in BCD.
+    fn add_decimal_to_register_a(&mut self, data: u8) {
+        let carry = if self.status.contains(CpuFlags::CARRY) { 1 } else { 0 };
+        let binary_sum = self.register_a.wrapping_add(data).wrapping_add(carry);
 
-                    self.program_counter = indirect_ref;
-                }
+        let mut low = (self.register_a & 0x0f) as u16 + (data & 0x0f) as u16 + carry as u16;
+        if low >= 0x0a {
+            low = ((low + 0x06) & 0x0f) + 0x10;
+        }
+        let mut sum = (self.register_a & 0xf0) as u16 + (data & 0xf0) as u16 + low;
 
-                // JSR - Jump to subroutine
-                0x20 => {
-                    // Add 2 to the program counter, which correspond to the 2 bytes that are read to get the address of
-                    // the subroutine.
-                    // Subtract 1 to account for the 1 that is added to it in the instruction RTS.
-                    self.stack_push_u16(self.program_counter + 2 - 1);
-                    let target_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = target_address;
-                }
-
-                // RTS - Return from subroutine
-                0x60 => {
-                    self.program_counter = self.stack_pop_u16() + 1;
-                }
-
-                // RTI - Return from interrupt
-                0x40 => {
-                    self.status.bits = self.stack_pop();
-                    self.status.remove(CpuFlags::BREAK);
-                    self.status.insert(CpuFlags::BREAK2);
-                    self.program_counter = self.stack_pop_u16();
-                }
-
-                // BNE - Branch on non equal
-                0xD0 => {
-                    self.branch(!self.status.contains(CpuFlags::ZERO));
-                }
-
-                // BVS - Branch on overflow set
-                0x70 => {
-                    self.branch(self.status.contains(CpuFlags::OVERFLOW));
-                }
-
-                // BVC - Branch on overflow clear
-                0x50 => {
-                    self.branch(!self.status.contains(CpuFlags::OVERFLOW));
-                }
-
-                // BMI - Branch on minus
-                0x30 => {
-                    self.branch(self.status.contains(CpuFlags::NEGATIVE));
-                }
-
-                // BEQ - Branch on equal
-                0xF0 => {
-                    self.branch(self.status.contains(CpuFlags::ZERO));
-                }
-
-                // BCS - Branch on carry set
-                0xB0 => {
-                    self.branch(self.status.contains(CpuFlags::CARRY));
-                }
-
-                // BCC - Branch on carry clear
-                0x90 => {
-                    self.branch(!self.status.contains(CpuFlags::CARRY));
-                }
-
-                // BPL - Branch on plus
-                0x10 => {
-                    self.branch(!self.status.contains(CpuFlags::NEGATIVE));
-                }
-
-                // BIT
-                0x24 | 0x2c => {
-                    self.bit(&opcode.mode);
-                }
-
-                /* Stores and loads */
-
-                // LDA
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    self.lda(&opcode.mode);
-                }
-
-                // LDX
-                0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-                    self.ldx(&opcode.mode);
-                }
-
-                // LDY
-                0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-                    self.ldy(&opcode.mode);
-                }
-
-                // STA
-                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&opcode.mode);
-                }
-
-                // STX - Store X register
-                0x86 | 0x96 | 0x8e => {
-                    let address = self.get_operand_address(&opcode.mode);
-                    self.mem_write(address, self.register_x);
-                }
-
-                // STY - Store Y register
-                0x84 | 0x94 | 0x8c => {
-                    let address = self.get_operand_address(&opcode.mode);
-                    self.mem_write(address, self.register_y);
-                }
-
-                /* Clear flags */
-
-                // CLD
-                0xD8 => self.status.remove(CpuFlags::DECIMAL_MODE),
-                // CLI
-                0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
-                // CLV
-                0xB8 => self.status.remove(CpuFlags::OVERFLOW),
-                // CLC
-                0x18 => self.clear_carry_flag(),
-                // SEC
-                0x38 => self.set_carry_flag(),
-                // SEI
-                0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
-                // SED
-                0xF8 => self.status.insert(CpuFlags::DECIMAL_MODE),
-
-                // TAX - Transfer Accumulator to X
-                0xAA => self.tax(),
-                // TAY - Transfer Accumulator to Y
-                0xA8 => {
-                    self.register_y = self.register_a;
-                    self.update_zero_and_negative_flags(self.register_y);
-                }
-                // TSX - Transfer stack pointer to X
-                0xBA => {
-                    self.register_x = self.stack_pointer;
-                    self.update_zero_and_negative_flags(self.register_x);
-                }
-                // TXA - Transfer X to A
-                0x8A => {
-                    self.register_a = self.register_x;
-                    self.update_zero_and_negative_flags(self.register_a);
-                }
-                // TXS - Transfer X to stack pointer
-                0x9A => {
-                    self.stack_pointer = self.register_x;
-                }
-                // TYA - Transfer Y to A
-                0x98 => {
-                    self.register_a = self.register_y;
-                    self.update_zero_and_negative_flags(self.register_a);
-                }
-
-                /* Stack */
-
-                // PHA - Push accumulator
-                0x48 => self.stack_push(self.register_a),
-                // PLA
-                0x68 => self.pla(),
-                // PHP
-                0x08 => self.php(),
-                // PLP
-                0x28 => self.plp(),
-
-                /* Unofficial opcodes */
-
-                /* DCP */
-                0xc7 | 0xd7 | 0xCF | 0xdF | 0xdb | 0xd3 | 0xc3 => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let mut data = self.mem_read(addr);
-                    data = data.wrapping_sub(1);
-                    self.mem_write(addr, data);
-                    // self._update_zero_and_negative_flags(data);
-                    if data <= self.register_a {
-                        self.status.insert(CpuFlags::CARRY);
-                    }
-
-                    self.update_zero_and_negative_flags(self.register_a.wrapping_sub(data));
-                }
-
-                /* RLA */
-                0x27 | 0x37 | 0x2F | 0x3F | 0x3b | 0x33 | 0x23 => {
-                    let data = self.rol(&opcode.mode);
-                    self.and_with_register_a(data);
-                }
-
-                /* SLO */ //todo tests
-                0x07 | 0x17 | 0x0F | 0x1f | 0x1b | 0x03 | 0x13 => {
-                    let data = self.asl(&opcode.mode);
-                    self.or_with_register_a(data);
-                }
-
-                /* SRE */ //todo tests
-                0x47 | 0x57 | 0x4F | 0x5f | 0x5b | 0x43 | 0x53 => {
-                    let data = self.lsr(&opcode.mode);
-                    self.xor_with_register_a(data);
-                }
-
-                /* SKB */
-                0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
-                    /* 2 byte NOP (immediate ) */
-                    // todo: might be worth doing the read
-                }
-
-                /* AXS */
-                0xCB => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    let x_and_a = self.register_x & self.register_a;
-                    let result = x_and_a.wrapping_sub(data);
-
-                    if data <= x_and_a {
-                        self.status.insert(CpuFlags::CARRY);
-                    }
-                    self.update_zero_and_negative_flags(result);
-
-                    self.register_x = result;
-                }
-
-                /* ARR */
-                0x6B => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_register_a(data);
-                    self.ror_accumulator();
-                    //todo: registers
-                    let result = self.register_a;
-                    let bit_5 = (result >> 5) & 1;
-                    let bit_6 = (result >> 6) & 1;
-
-                    if bit_6 == 1 {
-                        self.status.insert(CpuFlags::CARRY)
-                    } else {
-                        self.status.remove(CpuFlags::CARRY)
-                    }
-
-                    if bit_5 ^ bit_6 == 1 {
-                        self.status.insert(CpuFlags::OVERFLOW);
-                    } else {
-                        self.status.remove(CpuFlags::OVERFLOW);
-                    }
-
-                    self.update_zero_and_negative_flags(result);
-                }
-
-                /* unofficial SBC */
-                0xeb => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.sub_from_register_a(data);
-                }
-
-                /* ANC */
-                0x0b | 0x2b => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_register_a(data);
-                    if self.status.contains(CpuFlags::NEGATIVE) {
-                        self.status.insert(CpuFlags::CARRY);
-                    } else {
-                        self.status.remove(CpuFlags::CARRY);
-                    }
-                }
-
-                /* ALR */
-                0x4b => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_register_a(data);
-                    self.lsr_accumulator();
-                }
-
-                //todo: test for everything below
-
-                /* NOP read */
-                0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c
-                | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let _data = self.mem_read(addr);
-                    /* do nothing */
-                }
-
-                /* RRA */
-                0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
-                    let data = self.ror(&opcode.mode);
-                    self.add_to_register_a(data);
-                }
-
-                /* ISB */
-                0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
-                    let data = self.inc(&opcode.mode);
-                    self.sub_from_register_a(data);
-                }
-
-                /* NOPs */
-                0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2
-                | 0xf2 => { /* do nothing */ }
-
-                0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => { /* do nothing */ }
-
-                /* LAX */
-                0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.set_register_a(data);
-                    self.register_x = self.register_a;
-                }
-
-                /* SAX */
-                0x87 | 0x97 | 0x8f | 0x83 => {
-                    let data = self.register_a & self.register_x;
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, data);
-                }
-
-                /* LXA */
-                0xab => {
-                    self.lda(&opcode.mode);
-                    self.tax();
-                }
-
-                /* XAA */
-                0x8b => {
-                    self.register_a = self.register_x;
-                    self.update_zero_and_negative_flags(self.register_a);
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_register_a(data);
-                }
-
-                /* LAS */
-                0xbb => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let mut data = self.mem_read(addr);
-                    data = data & self.stack_pointer;
-                    self.register_a = data;
-                    self.register_x = data;
-                    self.stack_pointer = data;
-                    self.update_zero_and_negative_flags(data);
-                }
-
-                /* TAS */
-                0x9b => {
-                    let data = self.register_a & self.register_x;
-                    self.stack_pointer = data;
-                    let mem_address =
-                        self.mem_read_u16(self.program_counter) + self.register_y as u16;
-
-                    let data = ((mem_address >> 8) as u8 + 1) & self.stack_pointer;
-                    self.mem_write(mem_address, data)
-                }
-
-                /* AHX  Indirect Y */
-                0x93 => {
-                    let pos: u8 = self.mem_read(self.program_counter);
-                    let mem_address = self.mem_read_u16(pos as u16) + self.register_y as u16;
-                    let data = self.register_a & self.register_x & (mem_address >> 8) as u8;
-                    self.mem_write(mem_address, data)
-                }
+        self.status.set(CpuFlags::ZERO, binary_sum == 0);
+        self.status.set(CpuFlags::NEGATIVE, sum & 0x80 != 0);
+        self.status.set(CpuFlags::OVERFLOW, (data as u16 ^ sum) & (self.register_a as u16 ^ sum) & 0x80 != 0);
 
-                /* AHX Absolute Y*/
-                0x9f => {
-                    let mem_address =
-                        self.mem_read_u16(self.program_counter) + self.register_y as u16;
-
-                    let data = self.register_a & self.register_x & (mem_address >> 8) as u8;
-                    self.mem_write(mem_address, data)
-                }
-
-                /* SHX */
-                0x9e => {
-                    let mem_address =
-                        self.mem_read_u16(self.program_counter) + self.register_y as u16;
-
-                    // todo if cross page boundry {
-                    //     mem_address &= (self.x as u16) << 8;
-                    // }
-                    let data = self.register_x & ((mem_address >> 8) as u8 + 1);
-                    self.mem_write(mem_address, data)
-                }
-
-                /* SHY */
-                0x9c => {
-                    let mem_address =
-                        self.mem_read_u16(self.program_counter) + self.register_x as u16;
-                    let data = self.register_y & ((mem_address >> 8) as u8 + 1);
-                    self.mem_write(mem_address, data)
-                }
-
-                // NOP - No operation
-                0xEA => {}
-                // BRK - Break
-                0x00 => return,
-            }
-
-            // Move the program counter, if it has not been modified by the current instruction.
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.len - 1) as u16;
-            }
-        }
-    }
-
-    fn update_zero_and_negative_flags(&mut self, result: u8) {
-        // Set the CPU flag corresponding to value equal to zero
-        if result == 0 {
-            self.status.insert(CpuFlags::ZERO);
-        } else {
-            self.status.remove(CpuFlags::ZERO);
-        }
-
-        // Set the CPU flag corresponding to negative value
-        if (result & 0b1000_0000) != 0 {
-            self.status.insert(CpuFlags::NEGATIVE);
-        } else {
-            self.status.remove(CpuFlags::NEGATIVE);
+        if sum >= 0xa0 {
+            sum += 0x60;
         }
+        self.status.set(CpuFlags::CARRY, sum >= 0x100);
+        self.register_a = sum as u8;
+        self.fix_decimal_flags();
     }
 
-    /* Arithmetic */
-
-    // Add a value to the register A, taking into account the carry and overflow flags.
-    // http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
-    // We do not consider decimal mode, since it is not used by the NES processor.
-    fn add_to_register_a(&mut self, data: u8) {
-        let sum = self.register_a as u16
-                + data as u16
-                + (if self.status.contains(CpuFlags::CARRY) {
-                    1
-                } else {
-                    0
-                }) as u16;
-
-        // Set carry flag if needed
-        if sum > 0xff {
-            self.status.insert(CpuFlags::CARRY);
-        } else {
-            self.status.remove(CpuFlags::CARRY);
-        }
-
-        // Set overflow flag if needed
-        let result = sum as u8;
-        if (data ^ result) & (result ^ self.register_a) & 0x80 != 0 {
-            self.status.insert(CpuFlags::OVERFLOW);
-        } else {
-            self.status.remove(CpuFlags::OVERFLOW);
+    // The 65C02 sets N and Z from the BCD result, at the cost of an extra cycle (a dummy read of the operand).
+    fn fix_decimal_flags(&mut self) {
+        if self.model == CpuModel::Wdc65C02 {
+            self.update_zero_and_negative_flags(self.register_a);
+            self.read(self.program_counter);
+            self.add_cycles(1);
         }
-
-        self.set_register_a(result);
     }
 
-    // ADC - Add and carry
-    fn adc(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(&mode);
-        let value = self.mem_read(address);
-        self.add_to_register_a(value);
-    }
+    // Subtract a value from the register A, taking into account the borrow (inverted carry) flag.
+    fn sub_from_register_a(&mut self, data: u8) {
+        let register_a = self.register_a;
+        let borrow = if self.status.contains(CpuFlags::CARRY) { 0 } else { 1 };
 
-    // SBC - subtract and carry
-    fn sbc(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(&mode);
-        let value = self.mem_read(address);
         // The quantity "((data as i8).wrapping_neg().wrapping_sub(1)) as u8" is the ones-complement of data, used to
         // compute the subtraction as an addition, as explained in:
         //      http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
@@ -830,39 +1028,84 @@ impl CPU {
         //      = A - N - (1-C) + 256
         //      = A + (255-N) + C
         //      = A + (ones complement of N) + C
-        // The addition of C is performed inside "add_to_register_a", so we need to compute the ones complemento of N.
+        // The addition of C is performed inside "add_binary_to_register_a", so we need to compute the ones complemento of N.
         // In the reference for the emulator, the ones-complement is referred to as !N, but we still need to consider the 
         // borrow/carry flag, which is where the wrapping_sub(1) commes in.
-        self.add_to_register_a(((value as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        self.add_binary_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+
+        // In decimal mode, the NMOS 6502 sets all the flags from the binary subtraction, and only adjusts the result.
+        // The 65C02 adjusts the whole difference instead of the digits, which gives a different result for invalid
+        // BCD operands, and sets N and Z from it: http://www.6502.org/tutorials/decimal_mode.html#A
+        if self.decimal_arithmetic() {
+            let low = (register_a & 0x0f) as i16 - (data & 0x0f) as i16 - borrow;
+            let mut result;
+            if self.model == CpuModel::Wdc65C02 {
+                result = register_a as i16 - data as i16 - borrow;
+                if result < 0 {
+                    result -= 0x60;
+                }
+                if low < 0 {
+                    result -= 0x06;
+                }
+            } else {
+                let low = if low < 0 { ((low - 0x06) & 0x0f) - 0x10 } else { low };
+                result = (register_a & 0xf0) as i16 - (data & 0xf0) as i16 + low;
+                if result < 0 {
+                    result -= 0x60;
+                }
+            }
+            self.register_a = result as u8;
+            self.fix_decimal_flags();
+        }
+    }
+
+    // ADC - Add and carry
+    fn adc(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
+        self.add_to_register_a(value);
+        Ok(())
+    }
+
+    // SBC - subtract and carry
+    fn sbc(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
+        self.sub_from_register_a(value);
+        Ok(())
     }
 
     // AND - bitwise AND with accumulator
-    fn and(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(&mode);
-        let value = self.mem_read(address);
+    fn and(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
         self.set_register_a(value & self.register_a);
+        Ok(())
     }
 
     // EOR - bitwise exclusive OR with accumulator
-    fn eor(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(&mode);
-        let value = self.mem_read(address);
+    fn eor(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
         self.set_register_a(value ^ self.register_a);
+        Ok(())
     }
 
     // ORA - bitwise OR with accumulator
-    fn ora(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(&mode);
-        let value = self.mem_read(address);
+    fn ora(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
         self.set_register_a(value | self.register_a);
+        Ok(())
     }
 
     /* Shifts */
 
     // ASL - Arithmetic shift left
-    fn asl(&mut self, mode: &AddressingMode) -> u8 {
-        let address = self.get_operand_address(&mode);
-        let mut data = self.mem_read(address);
+    fn asl(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let original = self.read(address);
+        let mut data = original;
 
         if data >> 7 == 1 {
             self.set_carry_flag();
@@ -871,12 +1114,12 @@ impl CPU {
         }
 
         data = data << 1;
-        self.mem_write(address, data);
+        self.write_modified(address, original, data);
         self.update_zero_and_negative_flags(data);
-        data
+        Ok(data)
     }
     // ASL - Arithmetic shift in the accumulator
-    fn asl_accumulator(&mut self) {
+    fn asl_accumulator(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         let mut data = self.register_a;
 
         if data >> 7 == 1 {
@@ -887,12 +1130,14 @@ impl CPU {
 
         data = data << 1;
         self.set_register_a(data);
+        Ok(())
     }
 
     // LSR - Logical shift right
-    fn lsr(&mut self, mode: &AddressingMode) -> u8 {
-        let address = self.get_operand_address(&mode);
-        let mut data = self.mem_read(address);
+    fn lsr(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let original = self.read(address);
+        let mut data = original;
 
         if data & 1 == 1 {
             self.set_carry_flag();
@@ -901,12 +1146,12 @@ impl CPU {
         }
 
         data = data >> 1;
-        self.mem_write(address, data);
+        self.write_modified(address, original, data);
         self.update_zero_and_negative_flags(data);
-        data
+        Ok(data)
     }
     // LSR - Logical shift right in the accumulator
-    fn lsr_accumulator(&mut self) {
+    fn lsr_accumulator(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         let mut data = self.register_a;
 
         if data & 1 == 1 {
@@ -917,12 +1162,14 @@ impl CPU {
 
         data = data >> 1;
         self.set_register_a(data);
+        Ok(())
     }
 
     // ROL - Rotate left
-    fn rol(&mut self, mode: &AddressingMode) -> u8 {
-        let address = self.get_operand_address(&mode);
-        let mut data = self.mem_read(address);
+    fn rol(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let original = self.read(address);
+        let mut data = original;
         let old_carry = self.status.contains(CpuFlags::CARRY);
         
         if data >> 7 == 1 {
@@ -935,12 +1182,12 @@ impl CPU {
             data = data | 1;
         }
 
-        self.mem_write(address, data);
+        self.write_modified(address, original, data);
         self.update_zero_and_negative_flags(data);
-        data
+        Ok(data)
     }
     // ROL - Rotate left the accumulator
-    fn rol_accumulator(&mut self) {
+    fn rol_accumulator(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         let mut data = self.register_a;
         let old_carry = self.status.contains(CpuFlags::CARRY);
         
@@ -955,12 +1202,14 @@ impl CPU {
         }
 
         self.set_register_a(data);
+        Ok(())
     }
 
     // ROR - Rotate right
-    fn ror(&mut self, mode: &AddressingMode) -> u8 {
-        let address = self.get_operand_address(&mode);
-        let mut data = self.mem_read(address);
+    fn ror(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let original = self.read(address);
+        let mut data = original;
         let old_carry = self.status.contains(CpuFlags::CARRY);
         
         if data & 1 == 1 {
@@ -973,12 +1222,12 @@ impl CPU {
             data = data | 0b10000000;
         }
 
-        self.mem_write(address, data);
+        self.write_modified(address, original, data);
         self.update_zero_and_negative_flags(data);
-        data
+        Ok(data)
     }
     // ROR - Rotate right the accumulator
-    fn ror_accumulator(&mut self) {
+    fn ror_accumulator(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         let mut data = self.register_a;
         let old_carry = self.status.contains(CpuFlags::CARRY);
         
@@ -993,62 +1242,83 @@ impl CPU {
         }
 
         self.set_register_a(data);
+        Ok(())
     }
 
     // INC - Increment memory
-    fn inc(&mut self, mode: &AddressingMode) -> u8 {
-        let address = self.get_operand_address(&mode);
-        let mut data = self.mem_read(address);
+    fn inc(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let original = self.read(address);
+        let mut data = original;
 
         data = data.wrapping_add(1);
 
-        self.mem_write(address, data);
+        self.write_modified(address, original, data);
         self.update_zero_and_negative_flags(data);
-        data
+        Ok(data)
     }
 
     // INX - Increment X Register
-    fn inx(&mut self) {
+    fn inx(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         // Add 1 and wrap if there is overflow.
         self.register_x = self.register_x.wrapping_add(1);
         self.update_zero_and_negative_flags(self.register_x);
+        Ok(())
     }
 
     // INY - Increment Y Register
-    fn iny(&mut self) {
+    fn iny(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         // Add 1 and wrap if there is overflow.
         self.register_y = self.register_y.wrapping_add(1);
         self.update_zero_and_negative_flags(self.register_y);
+        Ok(())
     }
 
     // DEC - Decrement memory
-    fn dec(&mut self, mode: &AddressingMode) -> u8 {
-        let address = self.get_operand_address(&mode);
-        let mut data = self.mem_read(address);
+    fn dec(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let original = self.read(address);
+        let mut data = original;
 
         data = data.wrapping_sub(1);
 
-        self.mem_write(address, data);
+        self.write_modified(address, original, data);
         self.update_zero_and_negative_flags(data);
-        data
+        Ok(data)
     }
 
     // DEX - Decrement X Register
-    fn dex(&mut self) {
+    fn dex(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         self.register_x = self.register_x.wrapping_sub(1);
         self.update_zero_and_negative_flags(self.register_x);
+        Ok(())
     }
 
     // DEY - Decrement Y Register
-    fn dey(&mut self) {
+    fn dey(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         self.register_y = self.register_y.wrapping_sub(1);
         self.update_zero_and_negative_flags(self.register_y);
+        Ok(())
     }
 
     // CMP - Compare accumulator
-    fn compare(&mut self, mode: &AddressingMode, compare_with: u8) {
-        let address = self.get_operand_address(&mode);
-        let data = self.mem_read(address);
+    fn cmp(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        self.compare(mode, self.register_a)
+    }
+
+    // CPY - Compare Y register
+    fn cpy(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        self.compare(mode, self.register_y)
+    }
+
+    // CPX - Compare X register
+    fn cpx(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        self.compare(mode, self.register_x)
+    }
+
+    fn compare(&mut self, mode: &AddressingMode, compare_with: u8) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let data = self.read(address);
 
         if data <= compare_with {
             self.status.insert(CpuFlags::CARRY);
@@ -1057,26 +1327,161 @@ impl CPU {
         }
 
         self.update_zero_and_negative_flags(compare_with.wrapping_sub(data));
+        Ok(())
     }
 
     /* Branching */
 
+    // JMP absolute
+    fn jmp_absolute(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        let mem_address = self.read_u16(self.program_counter);
+        self.program_counter = mem_address;
+        Ok(())
+    }
+
+    // JMP indirect
+    fn jmp_indirect(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        let mem_address = self.read_u16(self.program_counter);
+
+        // Manage the case in which we are reading the last byte of a page, as explained in 
+        //      http://www.6502.org/tutorials/6502opcodes.html#JMP
+        let indirect_ref = if mem_address & 0x00FF == 0x00FF {
+            let lo = self.read(mem_address);
+            let hi = self.read(mem_address & 0xFF00);
+            (hi as u16) << 8 | (lo as u16)
+        } else {
+            self.read_u16(mem_address)
+        };
+
+        self.program_counter = indirect_ref;
+        Ok(())
+    }
+
+    // JSR - Jump to subroutine
+    fn jsr(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        // The low byte of the target is read before pushing the return address, and the high byte after it.
+        let lo = self.read(self.program_counter) as u16;
+        self.stack_dummy_read();
+        // Add 2 to the program counter, which correspond to the 2 bytes that are read to get the address of
+        // the subroutine.
+        // Subtract 1 to account for the 1 that is added to it in the instruction RTS.
+        self.stack_push_u16(self.program_counter.wrapping_add(2 - 1));
+        let hi = self.read(self.program_counter.wrapping_add(1)) as u16;
+        self.program_counter = hi << 8 | lo;
+        Ok(())
+    }
+
+    // RTS - Return from subroutine
+    fn rts(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_dummy_read();
+        let return_address = self.stack_pop_u16();
+        // The program counter is incremented in a cycle of its own, which reads the last byte of the JSR
+        self.read(return_address);
+        self.program_counter = return_address.wrapping_add(1);
+        Ok(())
+    }
+
+    // RTI - Return from interrupt
+    fn rti(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_dummy_read();
+        self.status.bits = self.stack_pop();
+        self.status.remove(CpuFlags::BREAK);
+        self.status.insert(CpuFlags::BREAK2);
+        self.program_counter = self.stack_pop_u16();
+        Ok(())
+    }
+
+    // BRK - Break
+    fn brk(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        // The byte after BRK is skipped, so the return address is the address of BRK + 2
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.interrupt(interrupt::BRK);
+        Ok(())
+    }
+
+    // BNE - Branch on non equal
+    fn bne(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(!self.status.contains(CpuFlags::ZERO));
+        Ok(())
+    }
+
+    // BVS - Branch on overflow set
+    fn bvs(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(self.status.contains(CpuFlags::OVERFLOW));
+        Ok(())
+    }
+
+    // BVC - Branch on overflow clear
+    fn bvc(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(!self.status.contains(CpuFlags::OVERFLOW));
+        Ok(())
+    }
+
+    // BMI - Branch on minus
+    fn bmi(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(self.status.contains(CpuFlags::NEGATIVE));
+        Ok(())
+    }
+
+    // BEQ - Branch on equal
+    fn beq(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(self.status.contains(CpuFlags::ZERO));
+        Ok(())
+    }
+
+    // BCS - Branch on carry set
+    fn bcs(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(self.status.contains(CpuFlags::CARRY));
+        Ok(())
+    }
+
+    // BCC - Branch on carry clear
+    fn bcc(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(!self.status.contains(CpuFlags::CARRY));
+        Ok(())
+    }
+
+    // BPL - Branch on plus
+    fn bpl(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(!self.status.contains(CpuFlags::NEGATIVE));
+        Ok(())
+    }
+
+    // A taken branch costs one extra cycle, and another one if the target is in a different page than the
+    // next instruction.
     fn branch(&mut self, condition: bool) {
+        self.branch_with_offset_at(condition, self.program_counter);
+    }
+
+    // Branch with the offset stored at the given address, which is the last byte of the instruction.
+    // The offset is always read. During the extra cycles, the processor reads the next instruction, and then
+    // the target address before carrying to the high byte.
+    fn branch_with_offset_at(&mut self, condition: bool, offset_address: u16) {
+        let jump: i8 = self.read(offset_address) as i8;
         if condition {
-            let jump: i8 = self.mem_read(self.program_counter) as i8;
-            let jump_address = self
-                .program_counter
-                .wrapping_add(1)
-                .wrapping_add(jump as u16);
+            let next_instruction = offset_address.wrapping_add(1);
+            let jump_address = next_instruction.wrapping_add(jump as u16);
+
+            // Without a page crossing, the interrupts are not polled in the extra cycle, so an interrupt that
+            // arrives during it is delayed until after the next instruction.
+            let polled = (self.nmi_polled, self.irq_polled);
+            self.read(next_instruction);
+            self.add_cycles(1);
+            if page_crossed(next_instruction, jump_address) {
+                self.read(uncarried(next_instruction, jump_address));
+                self.add_cycles(1);
+            } else {
+                (self.nmi_polled, self.irq_polled) = polled;
+            }
 
             self.program_counter = jump_address;
         }
     }
 
     // BIT - test BITs
-    fn bit(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(&mode);
-        let value = self.mem_read(address);
+    fn bit(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
 
         let and = self.register_a & value;
 
@@ -1088,40 +1493,59 @@ impl CPU {
 
         self.status.set(CpuFlags::NEGATIVE, value & 0b10000000 > 0);
         self.status.set(CpuFlags::OVERFLOW, value & 0b01000000 > 0);
+        Ok(())
     }
 
     /* Stores and loads */
 
     // LDA - Load accumulator
-    fn lda(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(&mode);
-        let value = self.mem_read(address);
+    fn lda(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
 
         self.set_register_a(value);
+        Ok(())
     }
 
     // LDX - Load X register
-    fn ldx(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(mode);
-        let value = self.mem_read(address);
+    fn ldx(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
 
         self.register_x = value;
         self.update_zero_and_negative_flags(self.register_x);
+        Ok(())
     }
 
     // LDY - Load Y register
-    fn ldy(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(&mode);
-        let value = self.mem_read(address);
+    fn ldy(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
 
         self.register_y = value;
         self.update_zero_and_negative_flags(self.register_y);
+        Ok(())
     }
 
     // STA - Store accumulator (saves value in A to a given address in memory)
-    fn sta(&mut self, mode: &AddressingMode) {
-        let address = self.get_operand_address(mode);
-        self.mem_write(address, self.register_a);
+    fn sta(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        self.write(address, self.register_a);
+        Ok(())
+    }
+
+    // STX - Store X register
+    fn stx(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        self.write(address, self.register_x);
+        Ok(())
+    }
+
+    // STY - Store Y register
+    fn sty(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        self.write(address, self.register_y);
+        Ok(())
     }
 
     /* Clear flags */
@@ -1134,40 +1558,146 @@ impl CPU {
         self.status.remove(CpuFlags::CARRY);
     }
 
+    // CLD
+    fn cld(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.status.remove(CpuFlags::DECIMAL_MODE);
+        Ok(())
+    }
+
+    // CLI
+    fn cli(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.set_interrupt_disable_after_poll(false);
+        Ok(())
+    }
+
+    // CLV
+    fn clv(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.status.remove(CpuFlags::OVERFLOW);
+        Ok(())
+    }
+
+    // CLC
+    fn clc(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.clear_carry_flag();
+        Ok(())
+    }
+
+    // SEC
+    fn sec(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.set_carry_flag();
+        Ok(())
+    }
+
+    // SEI
+    fn sei(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.set_interrupt_disable_after_poll(true);
+        Ok(())
+    }
+
+    // Change the I flag in the last cycle of the instruction, after the interrupts have been polled with the old
+    // value. So, an interrupt can still be serviced right after SEI, and CLI lets it through after the next one.
+    fn set_interrupt_disable_after_poll(&mut self, interrupt_disable: bool) {
+        self.delayed_interrupt_disable = Some(self.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        self.status.set(CpuFlags::INTERRUPT_DISABLE, interrupt_disable);
+    }
+
+    // SED
+    fn sed(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.status.insert(CpuFlags::DECIMAL_MODE);
+        Ok(())
+    }
+
     // TAX - Transfer Accumulator to X
-    fn tax(&mut self) {
+    fn tax(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
         self.register_x = self.register_a;
         self.update_zero_and_negative_flags(self.register_x);
+        Ok(())
+    }
+
+    // TAY - Transfer Accumulator to Y
+    fn tay(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.register_y = self.register_a;
+        self.update_zero_and_negative_flags(self.register_y);
+        Ok(())
+    }
+
+    // TSX - Transfer stack pointer to X
+    fn tsx(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.register_x = self.stack_pointer;
+        self.update_zero_and_negative_flags(self.register_x);
+        Ok(())
+    }
+
+    // TXA - Transfer X to A
+    fn txa(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.register_a = self.register_x;
+        self.update_zero_and_negative_flags(self.register_a);
+        Ok(())
+    }
+
+    // TXS - Transfer X to stack pointer
+    fn txs(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_pointer = self.register_x;
+        Ok(())
+    }
+
+    // TYA - Transfer Y to A
+    fn tya(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.register_a = self.register_y;
+        self.update_zero_and_negative_flags(self.register_a);
+        Ok(())
+    }
+
+    // JAM - Lock up the CPU. The program counter stays on the opcode.
+    fn jam(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        let pc = self.program_counter.wrapping_sub(1);
+        self.program_counter = pc;
+        self.jammed = true;
+        Err(CpuError::Jammed { opcode: self.peek(pc), pc })
+    }
+
+    // NOP - No operation
+    fn nop(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        Ok(())
     }
 
     /* Stack */
 
+    // PHA - Push accumulator
+    fn pha(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_push(self.register_a);
+        Ok(())
+    }
+
     // PLA - Pull accumulator
-    fn pla(&mut self) {
+    fn pla(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_dummy_read();
         let data = self.stack_pop();
         self.set_register_a(data);
+        Ok(())
     }
 
     // PHP - Push processor status
-    fn php(&mut self) {
-        let mut flags = self.status.clone();
+    fn php(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        let mut flags = self.status;
         flags.insert(CpuFlags::BREAK);
         flags.insert(CpuFlags::BREAK2);
         self.stack_push(flags.bits());
+        Ok(())
     }
 
     // PLP - Pull processor status
-    fn plp(&mut self) {
+    fn plp(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_dummy_read();
+        // Like CLI and SEI, the new I flag is not seen by the poll of this instruction
+        self.delayed_interrupt_disable = Some(self.status.contains(CpuFlags::INTERRUPT_DISABLE));
         self.status.bits = self.stack_pop();
         self.status.remove(CpuFlags::BREAK);
         self.status.insert(CpuFlags::BREAK2);
+        Ok(())
     }
 
     /* Unofficial opcodes */
-    fn sub_from_register_a(&mut self, data: u8) {
-        self.add_to_register_a(((data as u8).wrapping_neg().wrapping_sub(1)) as u8);
-    }
-
     fn and_with_register_a(&mut self, data: u8) {
         self.set_register_a(data & self.register_a);
     }
@@ -1179,22 +1709,402 @@ impl CPU {
     fn or_with_register_a(&mut self, data: u8) {
         self.set_register_a(data | self.register_a);
     }
-}
 
+    // DCP
+    fn dcp(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let original = self.read(addr);
+        let data = original.wrapping_sub(1);
+        self.write_modified(addr, original, data);
+        // self._update_zero_and_negative_flags(data);
+        self.status.set(CpuFlags::CARRY, data <= self.register_a);
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::cartridge::test;
+        self.update_zero_and_negative_flags(self.register_a.wrapping_sub(data));
+        Ok(())
+    }
 
-    // Tests from section 3.1
+    // RLA
+    fn rla(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let data = self.rol(mode)?;
+        self.and_with_register_a(data);
+        Ok(())
+    }
 
-    #[test]
-    fn test_0xa9_lda_immediate_load_data() {
-        let bus = Bus::new(test::test_rom(vec![0xa9, 0x05, 0x00]));
+    // SLO
+    fn slo(&mut self, mode: &AddressingMode) -> Result<(), CpuError> { //todo tests
+        let data = self.asl(mode)?;
+        self.or_with_register_a(data);
+        Ok(())
+    }
+
+    // SRE
+    fn sre(&mut self, mode: &AddressingMode) -> Result<(), CpuError> { //todo tests
+        let data = self.lsr(mode)?;
+        self.xor_with_register_a(data);
+        Ok(())
+    }
+
+    // AXS
+    fn axs(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let data = self.read(addr);
+        let x_and_a = self.register_x & self.register_a;
+        let result = x_and_a.wrapping_sub(data);
+
+        self.status.set(CpuFlags::CARRY, data <= x_and_a);
+        self.update_zero_and_negative_flags(result);
+
+        self.register_x = result;
+        Ok(())
+    }
+
+    // ARR
+    fn arr(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let data = self.read(addr);
+        self.and_with_register_a(data);
+        self.ror_accumulator(&AddressingMode::NoneAddressing)?;
+        //todo: registers
+        let result = self.register_a;
+        let bit_5 = (result >> 5) & 1;
+        let bit_6 = (result >> 6) & 1;
+
+        if bit_6 == 1 {
+            self.status.insert(CpuFlags::CARRY)
+        } else {
+            self.status.remove(CpuFlags::CARRY)
+        }
+
+        if bit_5 ^ bit_6 == 1 {
+            self.status.insert(CpuFlags::OVERFLOW);
+        } else {
+            self.status.remove(CpuFlags::OVERFLOW);
+        }
+
+        self.update_zero_and_negative_flags(result);
+        Ok(())
+    }
+
+    // ANC
+    fn anc(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let data = self.read(addr);
+        self.and_with_register_a(data);
+        if self.status.contains(CpuFlags::NEGATIVE) {
+            self.status.insert(CpuFlags::CARRY);
+        } else {
+            self.status.remove(CpuFlags::CARRY);
+        }
+        Ok(())
+    }
+
+    // ALR
+    fn alr(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let data = self.read(addr);
+        self.and_with_register_a(data);
+        self.lsr_accumulator(&AddressingMode::NoneAddressing)?;
+        Ok(())
+    }
+
+    //todo: test for everything below
+
+    // NOP read
+    fn nop_read(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let _data = self.read(addr);
+        /* do nothing */
+        Ok(())
+    }
+
+    // NOP that keeps the bus busy after reading the operand (opcode 5C of the 65C02, which takes 8 cycles)
+    fn nop_long(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        for _ in 0..5 {
+            self.read(addr);
+        }
+        Ok(())
+    }
+
+    // RRA
+    fn rra(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let data = self.ror(mode)?;
+        self.add_to_register_a(data);
+        Ok(())
+    }
+
+    // ISB
+    fn isb(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let data = self.inc(mode)?;
+        self.sub_from_register_a(data);
+        Ok(())
+    }
+
+    // LAX
+    fn lax(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let data = self.read(addr);
+        self.set_register_a(data);
+        self.register_x = self.register_a;
+        Ok(())
+    }
+
+    // SAX
+    fn sax(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let data = self.register_a & self.register_x;
+        let (addr, _) = self.get_operand_address(mode)?;
+        self.write(addr, data);
+        Ok(())
+    }
+
+    // LXA - AND the operand with A, through the same unstable path as XAA, and transfer the result to X
+    fn lxa(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let data = self.read(addr);
+        let result = (self.register_a | self.magic_constant) & data;
+        self.register_x = result;
+        self.set_register_a(result);
+        Ok(())
+    }
+
+    // XAA - A = (A | magic constant) & X & operand
+    // The magic constant depends on the chip and on its temperature:
+    //      http://visual6502.org/wiki/index.php?title=6502_Opcode_8B_%28XAA,_ANE%29
+    fn xaa(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let data = self.read(addr);
+        self.set_register_a((self.register_a | self.magic_constant) & self.register_x & data);
+        Ok(())
+    }
+
+    // LAS
+    fn las(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (addr, _) = self.get_operand_address(mode)?;
+        let mut data = self.read(addr);
+        data &= self.stack_pointer;
+        self.register_a = data;
+        self.register_x = data;
+        self.stack_pointer = data;
+        self.update_zero_and_negative_flags(data);
+        Ok(())
+    }
+
+    // Store done by AHX, TAS, SHX and SHY. The value is ANDed with the high byte of the base address plus one.
+    // When the indexing crosses a page, the high byte of the target address is replaced by the value stored.
+    fn store_and_high_byte(&mut self, mode: &AddressingMode, value: u8) -> Result<(), CpuError> {
+        let (address, page_cross) = self.get_operand_address(mode)?;
+        let index = match mode {
+            AddressingMode::Absolute_X => self.register_x,
+            _ => self.register_y
+        };
+        let base = address.wrapping_sub(index as u16);
+        let data = value & ((base >> 8) as u8).wrapping_add(1);
+
+        let address = if page_cross {
+            (data as u16) << 8 | (address & 0x00FF)
+        } else {
+            address
+        };
+        self.write(address, data);
+        Ok(())
+    }
+
+    // TAS
+    fn tas(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_pointer = self.register_a & self.register_x;
+        self.store_and_high_byte(mode, self.register_a & self.register_x)
+    }
+
+    // AHX
+    fn ahx(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        self.store_and_high_byte(mode, self.register_a & self.register_x)
+    }
+
+    // SHX
+    fn shx(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        self.store_and_high_byte(mode, self.register_x)
+    }
+
+    // SHY
+    fn shy(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        self.store_and_high_byte(mode, self.register_y)
+    }
+
+    /* 65C02 instructions */
+
+    // JMP indirect, without the bug of the NMOS version when the address is the last byte of a page.
+    // Fixing it takes an extra cycle, in which the high byte of the operand is read again.
+    fn jmp_indirect_fixed(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        let mem_address = self.read_u16(self.program_counter);
+        self.read(self.program_counter.wrapping_add(1));
+        self.program_counter = self.read_u16(mem_address);
+        Ok(())
+    }
+
+    // JMP absolute indexed indirect. The indexing takes a cycle, in which the high byte of the operand is read again.
+    fn jmp_indirect_x(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        let base = self.read_u16(self.program_counter);
+        self.read(self.program_counter.wrapping_add(1));
+        self.program_counter = self.read_u16(base.wrapping_add(self.register_x as u16));
+        Ok(())
+    }
+
+    // BRA - Branch always
+    fn bra(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.branch(true);
+        Ok(())
+    }
+
+    // BIT immediate. Unlike the other modes, it only affects the zero flag.
+    fn bit_immediate(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let value = self.read(address);
+        self.status.set(CpuFlags::ZERO, self.register_a & value == 0);
+        Ok(())
+    }
+
+    // INC accumulator
+    fn inc_accumulator(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.set_register_a(self.register_a.wrapping_add(1));
+        Ok(())
+    }
+
+    // DEC accumulator
+    fn dec_accumulator(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.set_register_a(self.register_a.wrapping_sub(1));
+        Ok(())
+    }
+
+    // PHX - Push X register
+    fn phx(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_push(self.register_x);
+        Ok(())
+    }
+
+    // PLX - Pull X register
+    fn plx(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_dummy_read();
+        self.register_x = self.stack_pop();
+        self.update_zero_and_negative_flags(self.register_x);
+        Ok(())
+    }
+
+    // PHY - Push Y register
+    fn phy(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_push(self.register_y);
+        Ok(())
+    }
+
+    // PLY - Pull Y register
+    fn ply(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.stack_dummy_read();
+        self.register_y = self.stack_pop();
+        self.update_zero_and_negative_flags(self.register_y);
+        Ok(())
+    }
+
+    // STZ - Store zero
+    fn stz(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        self.write(address, 0);
+        Ok(())
+    }
+
+    // TSB - Test and set bits. The zero flag is set as in BIT, and the bits of A are set in memory.
+    fn tsb(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let data = self.read(address);
+        self.status.set(CpuFlags::ZERO, self.register_a & data == 0);
+        self.write_modified(address, data, data | self.register_a);
+        Ok(())
+    }
+
+    // TRB - Test and reset bits. The zero flag is set as in BIT, and the bits of A are cleared in memory.
+    fn trb(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let data = self.read(address);
+        self.status.set(CpuFlags::ZERO, self.register_a & data == 0);
+        self.write_modified(address, data, data & !self.register_a);
+        Ok(())
+    }
+
+    // Mask of the bit used by RMB, SMB, BBR and BBS, whose number is in bits 4-6 of the opcode.
+    fn opcode_bit_mask(&self) -> u8 {
+        1 << ((self.current_opcode >> 4) & 0b111)
+    }
+
+    // RMB - Reset memory bit
+    fn rmb(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let data = self.read(address);
+        self.write_modified(address, data, data & !self.opcode_bit_mask());
+        Ok(())
+    }
+
+    // SMB - Set memory bit
+    fn smb(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        let data = self.read(address);
+        self.write_modified(address, data, data | self.opcode_bit_mask());
+        Ok(())
+    }
+
+    // Read the zero page operand of BBR and BBS. The processor reads it twice.
+    fn read_bit_branch_operand(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let (address, _) = self.get_operand_address(mode)?;
+        self.read(address);
+        Ok(self.read(address))
+    }
+
+    // BBR - Branch on bit reset
+    fn bbr(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let data = self.read_bit_branch_operand(mode)?;
+        self.branch_with_offset_at(data & self.opcode_bit_mask() == 0, self.program_counter.wrapping_add(1));
+        Ok(())
+    }
+
+    // BBS - Branch on bit set
+    fn bbs(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let data = self.read_bit_branch_operand(mode)?;
+        self.branch_with_offset_at(data & self.opcode_bit_mask() != 0, self.program_counter.wrapping_add(1));
+        Ok(())
+    }
+
+    // WAI - Wait for interrupt. The instruction is repeated until an interrupt is requested, even if IRQs are
+    // disabled: in that case, the execution continues after WAI without servicing the interrupt.
+    fn wai(&mut self, _mode: &AddressingMode) -> Result<(), CpuError> {
+        self.read(self.program_counter);
+        if !self.nmi_pending && !self.irq_line && !self.bus.irq_line() {
+            self.program_counter = self.program_counter.wrapping_sub(1);
+        }
+        Ok(())
+    }
+
+    // STP - Stop the clock until the CPU is reset. It behaves like the KIL/JAM opcodes of the NMOS version.
+    fn stp(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        self.read(self.program_counter);
+        self.jam(mode)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test;
+    use crate::ram::Ram;
+    use crate::single_step::BusCycle;
+    use crate::single_step::LoggedRam;
+
+    // Tests from section 3.1
+
+    #[test]
+    fn test_0xa9_lda_immediate_load_data() {
+        let bus = Bus::new(test::test_rom(vec![0xa9, 0x05, 0x00]));
         let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_a, 0x05);
         assert!(cpu.status.bits() & 0b0000_0010 == 0x00);
@@ -1205,8 +2115,9 @@ mod test {
     fn test_0xa9_lda_zero_flag() {
         let bus = Bus::new(test::test_rom(vec![0xA9, 0x00, 0x00]));
         let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert!(cpu.status.bits() & 0b0000_0010 == 0b10);
     }
@@ -1215,8 +2126,9 @@ mod test {
     fn test_0xxx_tax_move_a_to_x() {
         let bus = Bus::new(test::test_rom(vec![0xA9, 0x0A, 0xAA, 0x00]));
         let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_x, 10);
     }
@@ -1225,8 +2137,9 @@ mod test {
     fn test_0xe8_inx_overflow() {
         let bus = Bus::new(test::test_rom(vec![0xA9, 0xFF, 0xAA, 0xE8, 0xE8, 0x00]));
         let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_x, 1);
     }
@@ -1235,8 +2148,9 @@ mod test {
     fn test_5_ops_together() {
         let bus = Bus::new(test::test_rom(vec![0xA9, 0xC0, 0xAA, 0xE8, 0x00]));
         let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_x, 0xC1);
     }
@@ -1247,10 +2161,816 @@ mod test {
     fn test_lda_from_memory() {
         let bus = Bus::new(test::test_rom(vec![0xa5, 0x10, 0x00]));
         let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
         cpu.mem_write(0x10, 0x55);
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.register_a, 0x55);
     }
+
+    // Cycle counting
+
+    #[test]
+    fn test_cycles_page_cross_penalty() {
+        // LDX #$01 ; LDA $80FF,X ; LDA $8000,X
+        let bus = Bus::new(test::test_rom(vec![0xa2, 0x01, 0xbd, 0xff, 0x80, 0xbd, 0x00, 0x80, 0x00]));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.cycles, 2 + 5 + 4);
+    }
+
+    #[test]
+    fn test_cycles_store_has_no_page_cross_penalty() {
+        // LDX #$01 ; STA $01FF,X
+        let bus = Bus::new(test::test_rom(vec![0xa2, 0x01, 0x9d, 0xff, 0x01, 0x00]));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.cycles, 2 + 5);
+    }
+
+    #[test]
+    fn test_cycles_branch_penalties() {
+        // LDA #$01 ; BNE +0 (taken, same page) ; BEQ +0 (not taken)
+        let bus = Bus::new(test::test_rom(vec![0xa9, 0x01, 0xd0, 0x00, 0xf0, 0x00, 0x00]));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.cycles, 2 + 3 + 2);
+
+        // LDA #$01 ; BNE +$7F (taken, to a new page)
+        let mut bus = Bus::new(test::test_rom(vec![]));
+        bus.mem_write(0xf0, 0xa9);
+        bus.mem_write(0xf1, 0x01);
+        bus.mem_write(0xf2, 0xd0);
+        bus.mem_write(0xf3, 0x7f);
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+        cpu.program_counter = 0xf0;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.program_counter, 0x0173);
+        assert_eq!(cpu.cycles, 2 + 4);
+    }
+
+    // Interrupts
+
+    #[test]
+    fn test_brk_is_a_software_interrupt() {
+        // LDA #$01 ; BRK ; padding ; halt, with the BRK vector pointing to an RTI at 0x8010
+        let mut program = vec![0xa9, 0x01, 0x00, 0xff, 0x02];
+        program.resize(0x10, 0xea);
+        program.push(0x40);
+        program.resize(0x7FFE, 0x00);
+        program.extend(&[0x10, 0x80]);
+        let bus = Bus::new(test::test_rom(program));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x02);
+
+        let mut executed = vec![];
+        cpu.run_with_callback(|cpu| {
+            executed.push(cpu.program_counter);
+        }).unwrap();
+
+        assert_eq!(executed, vec![0x8000, 0x8002, 0x8010, 0x8004]);
+        assert_eq!(cpu.cycles, 2 + 7 + 6);
+        // Return address and status pushed with the B flag set
+        assert_eq!(cpu.mem_read_u16(STACK + STACK_RESET as u16 - 1), 0x8004);
+        assert_eq!(cpu.mem_read(STACK + STACK_RESET as u16 - 2), 0b0011_0100);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+    }
+
+    #[test]
+    fn test_nmi_pushes_state_and_jumps_to_vector() {
+        // The vector at 0xFFFA points to 0x8010, where the handler is just a BRK.
+        let mut program = vec![0xea; 0x10];
+        program.push(0x00);
+        program.resize(0x7FFA, 0x00);
+        program.extend(&[0x10, 0x80]);
+        let bus = Bus::new(test::test_rom(program));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+        cpu.status.insert(CpuFlags::INTERRUPT_DISABLE);
+
+        cpu.trigger_nmi();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.program_counter, 0x8010);
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(3));
+        // Return address, and the status with the B flag clear
+        assert_eq!(cpu.mem_read_u16(STACK + STACK_RESET as u16 - 1), 0x8000);
+        assert_eq!(cpu.mem_read(STACK + STACK_RESET as u16 - 2), 0b0010_0100);
+    }
+
+    #[test]
+    fn test_irq_is_masked_by_interrupt_disable_flag() {
+        // NOP ; NOP ; BRK, with the IRQ vector pointing to a BRK at 0x8010
+        let mut program = vec![0xea, 0xea, 0x00];
+        program.resize(0x10, 0xea);
+        program.push(0x00);
+        program.resize(0x7FFE, 0x00);
+        program.extend(&[0x10, 0x80]);
+
+        // The I flag is set at power up, so the interrupt is ignored
+        let bus = Bus::new(test::test_rom(program.clone()));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+        let mut executed = vec![];
+        cpu.set_irq_line(true);
+        cpu.run_with_callback(|cpu| {
+            executed.push(cpu.program_counter);
+        }).unwrap();
+
+        assert_eq!(executed, vec![0x8000, 0x8001, 0x8002]);
+
+        // With the I flag clear, the handler is entered before the first instruction
+        let bus = Bus::new(test::test_rom(program));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+        cpu.status.remove(CpuFlags::INTERRUPT_DISABLE);
+        let mut executed = vec![];
+        cpu.set_irq_line(true);
+        cpu.run_with_callback(|cpu| {
+            executed.push(cpu.program_counter);
+        }).unwrap();
+
+        assert_eq!(executed, vec![0x8010]);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert_eq!(cpu.mem_read(STACK + STACK_RESET as u16 - 2), 0b0010_0000);
+    }
+
+    // Stepping
+
+    #[test]
+    fn test_step_returns_instruction_summary() {
+        // LDA #$01 ; JMP $8010 ; ... ; NOP ; BRK
+        let mut program = vec![0xa9, 0x01, 0x4c, 0x10, 0x80];
+        program.resize(0x10, 0x00);
+        program.extend(&[0xea, 0x00]);
+        let bus = Bus::new(test::test_rom(program));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+
+        let result = cpu.step().unwrap();
+        assert_eq!(result, StepResult {
+            opcode: 0xa9,
+            pc_before: 0x8000,
+            pc_after: 0x8002,
+            cycles: 2,
+            interrupt: None,
+            halted: false
+        });
+
+        let result = cpu.step().unwrap();
+        assert_eq!((result.opcode, result.pc_after, result.cycles), (0x4c, 0x8010, 3));
+
+        let result = cpu.step().unwrap();
+        assert_eq!((result.opcode, result.pc_after, result.cycles), (0xea, 0x8011, 2));
+
+        // The halt opcode is fetched but not executed
+        let result = cpu.step().unwrap();
+        assert!(result.halted);
+        assert_eq!((result.pc_after, result.cycles), (0x8011, 0));
+
+        // The NMI vector points to 0x0000, which contains the halt opcode
+        cpu.trigger_nmi();
+        let result = cpu.step().unwrap();
+        assert_eq!(result.interrupt, Some(InterruptType::NMI));
+        assert!(result.halted);
+        assert_eq!((result.pc_before, result.cycles), (0x0000, 7));
+    }
+
+    #[test]
+    fn test_run_until_and_run_for_cycles() {
+        // INX ; JMP $8000
+        let bus = Bus::new(test::test_rom(vec![0xe8, 0x4c, 0x00, 0x80]));
+        let mut cpu = CPU::new(bus);
+
+        let result = cpu.run_until(|cpu| cpu.register_x == 3).unwrap();
+        assert_eq!(cpu.register_x, 3);
+        assert_eq!(result.pc_after, 0x8001);
+        assert_eq!(cpu.cycles, 3 * 2 + 2 * 3);
+
+        // Instructions are not split, so the CPU may run a few cycles past the target
+        let result = cpu.run_for_cycles(10).unwrap();
+        assert_eq!(result.opcode, 0xe8);
+        assert_eq!(cpu.cycles, 12 + 3 + 2 + 3 + 2);
+        assert_eq!(cpu.register_x, 5);
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture bench`
+    #[test]
+    #[ignore]
+    fn bench_instructions_per_second() {
+        // LDA $10,X ; ADC #$01 ; STA $0200,X ; INX ; BNE -10 ; INY ; JMP $8000
+        let program = vec![0xb5, 0x10, 0x69, 0x01, 0x9d, 0x00, 0x02, 0xe8, 0xd0, 0xf6, 0xc8, 0x4c, 0x00, 0x80];
+        let bus = Bus::new(test::test_rom(program));
+        let mut cpu = CPU::new(bus);
+
+        let instructions = 20_000_000;
+        let start = std::time::Instant::now();
+        for _ in 0..instructions {
+            cpu.step().unwrap();
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+
+        println!(
+            "{} instructions ({} cycles) in {:.3} s: {:.2} M instructions/s",
+            instructions, cpu.cycles, elapsed, instructions as f64 / elapsed / 1e6
+        );
+    }
+
+    #[test]
+    fn test_reset_loads_vector() {
+        let mut program = vec![0x00; 0x7FFC];
+        program.extend(&[0x34, 0x92]);
+        let bus = Bus::new(test::test_rom(program));
+        let mut cpu = CPU::new(bus);
+        cpu.register_a = 0x10;
+
+        cpu.reset();
+
+        assert_eq!(cpu.program_counter, 0x9234);
+        assert_eq!(cpu.register_a, 0);
+        assert_eq!(cpu.cycles, 7);
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+    }
+
+    #[test]
+    fn test_jam_locks_up_cpu_until_reset() {
+        let bus = Bus::new(test::test_rom(vec![0xe8, 0x02, 0xe8]));
+        let mut cpu = CPU::new(bus);
+        cpu.program_counter = 0x8000;
+
+        cpu.step().unwrap();
+        let error = cpu.step().unwrap_err();
+
+        assert_eq!(error, CpuError::Jammed { opcode: 0x02, pc: 0x8001 });
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.program_counter, 0x8001);
+        assert_eq!(cpu.step(), Err(error));
+        assert_eq!(cpu.register_x, 1);
+
+        cpu.reset();
+        assert!(!cpu.is_jammed());
+    }
+
+    #[test]
+    fn test_program_counter_wraps_around() {
+        // INX at $FFFF
+        let mut ram = Ram::new();
+        ram.mem_write(0xFFFF, 0xe8);
+        let mut cpu = CPU::new(ram);
+        cpu.program_counter = 0xFFFF;
+        cpu.step().unwrap();
+        assert_eq!((cpu.program_counter, cpu.register_x), (0x0000, 1));
+
+        // LDA $0300, with the high byte of the operand at $0000
+        cpu.bus.load(0xFFFE, &[0xad, 0x00]);
+        cpu.bus.load(0x0000, &[0x03]);
+        cpu.bus.mem_write(0x0300, 0x42);
+        cpu.program_counter = 0xFFFE;
+        cpu.step().unwrap();
+        assert_eq!((cpu.program_counter, cpu.register_a), (0x0001, 0x42));
+
+        // JSR $0300 pushes $0000, the address of its last byte
+        cpu.bus.mem_write(0xFFFE, 0x20);
+        cpu.program_counter = 0xFFFE;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0300);
+        assert_eq!(cpu.stack_pop_u16(), 0x0000);
+    }
+
+    #[test]
+    fn test_decimal_mode_adc_and_sbc() {
+        // SED; CLC; LDA #$09; ADC #$01; STA $10; LDA #$99; ADC #$01; SEC; LDA #$00; SBC #$01; BRK
+        let program = vec![
+            0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01, 0x85, 0x10, 0xa9, 0x99, 0x69, 0x01, 0x08,
+            0x38, 0xa9, 0x00, 0xe9, 0x01, 0x00
+        ];
+        let bus = Bus::new(test::test_rom(program.clone()));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+        cpu.decimal_mode = true;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x10);
+        // 99 + 01 = 00 with carry, but Z is set from the binary sum (0x9A)
+        let flags = cpu.stack_pop();
+        assert_eq!(flags & CpuFlags::CARRY.bits(), CpuFlags::CARRY.bits());
+        assert_eq!(flags & CpuFlags::ZERO.bits(), 0);
+        // 00 - 01 = 99 with borrow
+        assert_eq!(cpu.register_a, 0x99);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+        // The NES processor ignores the D flag
+        let bus = Bus::new(test::test_rom(program));
+        let mut cpu = CPU::new(bus);
+        cpu.halt_opcode = Some(0x00);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x0a);
+        assert_eq!(cpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn test_65c02_instructions() {
+        let mut ram = Ram::new();
+        ram.load(0x0200, &[
+            0xa9, 0xf0,       // LDA #$F0
+            0x64, 0x10,       // STZ $10
+            0x04, 0x10,       // TSB $10
+            0xa2, 0x12,       // LDX #$12
+            0xda,             // PHX
+            0x7a,             // PLY
+            0x87, 0x11,       // SMB0 $11
+            0x8f, 0x11, 0x02, // BBS0 $11,$0211
+            0xa9, 0x00,       // LDA #$00 (skipped)
+            0x80, 0x02,       // BRA $0215
+            0xa9, 0x00,       // LDA #$00 (skipped)
+            0xb2, 0x20,       // LDA ($20)
+            0x1a,             // INC A
+            0x6c, 0xff, 0x04, // JMP ($04FF)
+        ]);
+        ram.load(0x0020, &[0x00, 0x03]);
+        ram.mem_write(0x0300, 0x5a);
+        // The NMOS 6502 would take the high byte of the target from 0x0400
+        ram.load(0x04ff, &[0x30, 0x02]);
+        ram.mem_write(0x0400, 0x99);
+
+        let mut cpu = CPU::with_model(ram, CpuModel::Wdc65C02);
+        cpu.halt_opcode = Some(0x00);
+        cpu.program_counter = 0x0200;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.program_counter, 0x0230);
+        assert_eq!(cpu.register_a, 0x5b);
+        assert_eq!(cpu.register_y, 0x12);
+        assert_eq!(cpu.mem_read(0x10), 0xf0);
+        assert_eq!(cpu.mem_read(0x11), 0x01);
+    }
+
+    #[test]
+    fn test_65c02_indexed_shift_timing() {
+        // ASL $12F0,X and INC $12F0,X, without and with a page cross
+        let cycles = |model, opcode, index| {
+            let mut ram = Ram::new();
+            ram.load(0x0200, &[opcode, 0xf0, 0x12]);
+            let mut cpu = CPU::with_model(ram, model);
+            cpu.program_counter = 0x0200;
+            cpu.register_x = index;
+            cpu.step().unwrap().cycles
+        };
+        assert_eq!(cycles(CpuModel::Wdc65C02, 0x1e, 0x0f), 6);
+        assert_eq!(cycles(CpuModel::Wdc65C02, 0x1e, 0x10), 7);
+        assert_eq!(cycles(CpuModel::Wdc65C02, 0xfe, 0x0f), 7);
+        assert_eq!(cycles(CpuModel::Nmos6502, 0x1e, 0x0f), 7);
+        assert_eq!(cycles(CpuModel::Nmos6502, 0x1e, 0x10), 7);
+    }
+
+    #[test]
+    fn test_65c02_decimal_sbc() {
+        // SED; SEC; LDA #$00; SBC #$1B, with an invalid BCD operand
+        let run = |model| {
+            let mut ram = Ram::new();
+            ram.load(0x0200, &[0xf8, 0x38, 0xa9, 0x00, 0xe9, 0x1b]);
+            let mut cpu = CPU::with_model(ram, model);
+            cpu.program_counter = 0x0200;
+            for _ in 0..3 {
+                cpu.step().unwrap();
+            }
+            let result = cpu.step().unwrap();
+            (cpu.register_a, cpu.status.contains(CpuFlags::NEGATIVE), result.cycles)
+        };
+        // The 65C02 adjusts the whole difference, sets N from the result and takes an extra cycle
+        assert_eq!(run(CpuModel::Wdc65C02), (0x7f, false, 3));
+        // The NMOS 6502 adjusts each digit, and sets N from the binary difference (0xE5)
+        assert_eq!(run(CpuModel::Nmos6502), (0x8f, true, 2));
+    }
+
+    #[test]
+    fn test_dcp_and_axs_clear_carry_on_borrow() {
+        // SEC; LDA #$01; DCP $10 (with $10 = $05)
+        let mut ram = Ram::new();
+        ram.load(0x0200, &[0x38, 0xa9, 0x01, 0xc7, 0x10]);
+        ram.mem_write(0x10, 0x05);
+        let mut cpu = CPU::new(ram);
+        cpu.program_counter = 0x0200;
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.mem_read(0x10), 0x04);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+        // SEC; LDX #$FF; AXS #$05 (with A = $01)
+        cpu.bus.load(0x0200, &[0x38, 0xa2, 0xff, 0xcb, 0x05]);
+        cpu.program_counter = 0x0200;
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.register_x, 0xfc);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_unstable_xaa_and_lxa_use_magic_constant() {
+        // LDA #$10; LDX #$F3; XAA #$5F; STA $10; LXA #$A5
+        let mut ram = Ram::new();
+        ram.load(0x0200, &[0xa9, 0x10, 0xa2, 0xf3, 0x8b, 0x5f, 0x85, 0x10, 0xab, 0xa5, 0x00]);
+        let mut cpu = CPU::with_model(ram, CpuModel::Nmos6502);
+        cpu.halt_opcode = Some(0x00);
+        cpu.program_counter = 0x0200;
+        cpu.magic_constant = 0xEE;
+        cpu.run().unwrap();
+
+        // (0x10 | 0xEE) & 0xF3 & 0x5F
+        assert_eq!(cpu.mem_read(0x10), 0x52);
+        // (0x52 | 0xEE) & 0xA5
+        assert_eq!(cpu.register_a, 0xa4);
+        assert_eq!(cpu.register_x, 0xa4);
+        assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_unstable_stores_corrupt_high_byte_on_page_cross() {
+        // LDX #$05; LDY #$20; SHX $0200,Y; SHX $02F0,Y; LDA #$FF; TAS $0300,Y
+        let mut ram = Ram::new();
+        ram.load(0x0400, &[
+            0xa2, 0x05, 0xa0, 0x20, 0x9e, 0x00, 0x02, 0x9e, 0xf0, 0x02, 0xa9, 0xff, 0x9b, 0x00, 0x03, 0x00
+        ]);
+        let mut cpu = CPU::with_model(ram, CpuModel::Nmos6502);
+        cpu.halt_opcode = Some(0x00);
+        cpu.program_counter = 0x0400;
+        cpu.run().unwrap();
+
+        // Without page crossing: X & (0x02 + 1) is stored at 0x0220
+        assert_eq!(cpu.mem_read(0x0220), 0x01);
+        // Crossing to 0x0310: the value is X & (0x02 + 1), which also replaces the high byte of the address
+        assert_eq!(cpu.mem_read(0x0310), 0x00);
+        assert_eq!(cpu.mem_read(0x0110), 0x01);
+        // TAS: S = A & X, and A & X & (0x03 + 1) is stored
+        assert_eq!(cpu.stack_pointer, 0x05);
+        assert_eq!(cpu.mem_read(0x0320), 0x04);
+        assert_eq!(cpu.cycles, 2 + 2 + 5 + 5 + 2 + 5);
+    }
+
+    #[test]
+    fn test_dummy_reads_and_double_writes() {
+        let mut ram = LoggedRam::new();
+        // INC $10,X; LDA $02FF,X
+        for (i, byte) in [0xf6, 0x10, 0xbd, 0xff, 0x02].iter().enumerate() {
+            ram.mem_write(0x0200 + i as u16, *byte);
+        }
+        ram.mem_write(0x0011, 0x41);
+        ram.take_log();
+
+        let mut cpu = CPU::with_model(ram, CpuModel::Nmos6502);
+        cpu.program_counter = 0x0200;
+        cpu.register_x = 1;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let read = |address, value| BusCycle { address, value, write: false };
+        let write = |address, value| BusCycle { address, value, write: true };
+        assert_eq!(cpu.bus.take_log(), vec![
+            // INC: dummy read of the base address, and the unmodified value written back
+            read(0x0200, 0xf6), read(0x0201, 0x10), read(0x0010, 0x00), read(0x0011, 0x41),
+            write(0x0011, 0x41), write(0x0011, 0x42),
+            // LDA: the page is crossed, so the address before the carry is read first
+            read(0x0202, 0xbd), read(0x0203, 0xff), read(0x0204, 0x02), read(0x0200, 0xf6), read(0x0300, 0x00),
+        ]);
+    }
+
+    // Memory that counts the cycles it has been ticked, with interrupt lines asserted from a given cycle on
+    struct TestBus {
+        ram: Ram,
+        ticks: u64,
+        irq_from: Option<u64>,
+        nmi_from: Option<u64>
+    }
+
+    impl TestBus {
+        fn new(ram: Ram) -> Self {
+            TestBus { ram, ticks: 0, irq_from: None, nmi_from: None }
+        }
+    }
+
+    impl Mem for TestBus {
+        fn peek(&self, address: u16) -> u8 {
+            self.ram.peek(address)
+        }
+
+        fn mem_write(&mut self, address: u16, data: u8) {
+            self.ram.mem_write(address, data);
+        }
+
+        fn tick(&mut self) {
+            self.ticks += 1;
+        }
+
+        fn nmi_line(&self) -> bool {
+            self.nmi_from.is_some_and(|cycle| self.ticks >= cycle)
+        }
+
+        fn irq_line(&self) -> bool {
+            self.irq_from.is_some_and(|cycle| self.ticks >= cycle)
+        }
+    }
+
+    // Execute one instruction, with the operands and the memory filled with a fixed pattern. Returns the cycles and
+    // the final program counter, or None if the opcode locks up the CPU.
+    fn run_opcode(model: CpuModel, opcode: u8, status: u8, cycle_stepped: bool) -> Option<(u64, u16)> {
+        let pattern: Vec<u8> = (0..0x10000_u32).map(|i| (i * 7 + i / 251) as u8).collect();
+        let mut ram = Ram::new();
+        ram.load(0x0000, &pattern);
+        ram.load(0x12F0, &[opcode]);
+
+        let mut cpu = CPU::with_model(TestBus::new(ram), model);
+        cpu.cycle_stepped = cycle_stepped;
+        cpu.program_counter = 0x12F0;
+        cpu.register_a = 0x5A;
+        cpu.register_x = 0x80;
+        cpu.register_y = 0xC0;
+        cpu.stack_pointer = 0xF0;
+        cpu.status = CpuFlags::from_bits_truncate(status);
+
+        let result = cpu.step().ok()?;
+        assert_eq!(result.cycles, cpu.cycles);
+        // Only the cycle-stepped CPU ticks the bus, once per cycle
+        assert_eq!(cpu.bus.ticks, if cycle_stepped { cpu.cycles } else { 0 });
+        Some((cpu.cycles, cpu.program_counter))
+    }
+
+    #[test]
+    fn test_cycle_stepped_matches_instruction_timing() {
+        for &model in [CpuModel::Nmos6502, CpuModel::Wdc65C02].iter() {
+            for opcode in 0..=0xFF {
+                // With every flag clear and set, to take and skip every branch
+                for &status in [0x00, 0xFF].iter() {
+                    let instruction_stepped = run_opcode(model, opcode, status, false);
+                    let cycle_stepped = run_opcode(model, opcode, status, true);
+                    assert_eq!(instruction_stepped, cycle_stepped, "{:?} opcode {:02X} P={:02X}", model, opcode, status);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_opcode_metadata_matches_execution() {
+        for &model in [CpuModel::Nmos6502, CpuModel::Wdc65C02].iter() {
+            for opcode in opcodes::instruction_set(model) {
+                // The penalty is the difference between an index that crosses a page and one that does not, from a
+                // base address of 0x1240
+                let indexed = matches!(
+                    opcode.mode,
+                    AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y
+                );
+                if indexed {
+                    let cycles = |index: u8| {
+                        let mut ram = Ram::new();
+                        ram.load(0x0040, &[0x40, 0x12]);
+                        ram.load(0x0200, &[opcode.code, 0x40, 0x12]);
+                        let mut cpu = CPU::with_model(TestBus::new(ram), model);
+                        cpu.program_counter = 0x0200;
+                        cpu.register_x = index;
+                        cpu.register_y = index;
+                        cpu.step().unwrap();
+                        cpu.cycles
+                    };
+                    assert_eq!(
+                        cycles(0xD0) - cycles(0x10), opcode.page_cross_penalty as u64,
+                        "{:?} opcode {:02X}", model, opcode.code
+                    );
+                }
+
+                // Only the flags in the metadata change, whatever the state (the 65C02 also clears D in BRK)
+                let mut changeable = opcode.flags;
+                if model == CpuModel::Wdc65C02 && opcode.code == 0x00 {
+                    changeable.insert(CpuFlags::DECIMAL_MODE);
+                }
+                for &status in [0x00, 0xFF].iter() {
+                    for &value in [0x00, 0x01, 0x7F, 0x80, 0xFF].iter() {
+                        let mut ram = Ram::new();
+                        ram.load(0x0000, &vec![value; 0x10000]);
+                        ram.load(0x0200, &[opcode.code, 0x40, 0x12]);
+                        let mut cpu = CPU::with_model(TestBus::new(ram), model);
+                        cpu.program_counter = 0x0200;
+                        cpu.register_a = value;
+                        cpu.register_x = value;
+                        cpu.register_y = value;
+                        cpu.status = CpuFlags::from_bits_truncate(status);
+
+                        if cpu.step().is_ok() {
+                            let changed = (status ^ cpu.status.bits) & !(CpuFlags::BREAK | CpuFlags::BREAK2).bits;
+                            assert_eq!(
+                                changed & !changeable.bits, 0,
+                                "{:?} opcode {:02X} P={:02X} value {:02X}", model, opcode.code, status, value
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycle_stepped_bus_accesses() {
+        let mut ram = LoggedRam::new();
+        // 0200: JSR $0210; BEQ $01F0 (taken, to a different page)
+        // 0210: PHA; PLA; RTS
+        for (i, byte) in [0x20, 0x10, 0x02, 0xf0, 0xeb].iter().enumerate() {
+            ram.mem_write(0x0200 + i as u16, *byte);
+        }
+        for (i, byte) in [0x48, 0x68, 0x60].iter().enumerate() {
+            ram.mem_write(0x0210 + i as u16, *byte);
+        }
+        ram.take_log();
+
+        let mut cpu = CPU::with_model(ram, CpuModel::Nmos6502);
+        cpu.cycle_stepped = true;
+        cpu.program_counter = 0x0200;
+        cpu.register_a = 0x00;
+        cpu.status.insert(CpuFlags::ZERO);
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+
+        let read = |address, value| BusCycle { address, value, write: false };
+        let write = |address, value| BusCycle { address, value, write: true };
+        let log = cpu.bus.take_log();
+        assert_eq!(log, vec![
+            // JSR: the high byte of the target is read after pushing the return address
+            read(0x0200, 0x20), read(0x0201, 0x10), read(0x01FD, 0x00), write(0x01FD, 0x02), write(0x01FC, 0x02),
+            read(0x0202, 0x02),
+            // PHA and PLA: the next byte is read while decoding, and PLA reads the stack before incrementing
+            read(0x0210, 0x48), read(0x0211, 0x68), write(0x01FB, 0x00),
+            read(0x0211, 0x68), read(0x0212, 0x60), read(0x01FA, 0x00), read(0x01FB, 0x00),
+            // RTS: the last cycle increments the return address
+            read(0x0212, 0x60), read(0x0213, 0x00), read(0x01FB, 0x00), read(0x01FC, 0x02), read(0x01FD, 0x02),
+            read(0x0202, 0x02),
+            // BEQ: reads the next instruction, and then the target before fixing the high byte
+            read(0x0203, 0xf0), read(0x0204, 0xeb), read(0x0205, 0x00), read(0x02F0, 0x00),
+        ]);
+        assert_eq!(cpu.cycles, log.len() as u64);
+        assert_eq!(cpu.program_counter, 0x01F0);
+    }
+
+    // Scenarios of the cpu_interrupts_v2 test ROMs. The program is loaded at 0x0200, the NMI handler at 0x0500 and
+    // the IRQ handler at 0x0600, both made of NOPs. The interrupt lines of the bus are asserted from the given
+    // cycles, counted from the start of the program. Returns the addresses of the instructions executed.
+    fn run_interrupt_scenario(model: CpuModel, program: &[u8], status: u8, irq_from: Option<u64>, nmi_from: Option<u64>, steps: usize) -> (Vec<u16>, CPU<TestBus>) {
+        let mut ram = Ram::new();
+        ram.load(0x0200, program);
+        ram.load(0x0500, &[0xea; 0x10]);
+        ram.load(0x0600, &[0xea; 0x10]);
+        ram.mem_write_u16(0xFFFA, 0x0500);
+        ram.mem_write_u16(0xFFFE, 0x0600);
+
+        let mut bus = TestBus::new(ram);
+        bus.irq_from = irq_from;
+        bus.nmi_from = nmi_from;
+        let mut cpu = CPU::with_model(bus, model);
+        cpu.cycle_stepped = true;
+        cpu.program_counter = 0x0200;
+        cpu.status = CpuFlags::from_bits_truncate(status);
+
+        let executed = (0..steps).map(|_| cpu.step().unwrap().pc_before).collect();
+        (executed, cpu)
+    }
+
+    #[test]
+    fn test_cli_sei_and_plp_latency() {
+        const I_SET: u8 = 0b0010_0100;
+        const I_CLEAR: u8 = 0b0010_0000;
+
+        // CLI ; NOP ; NOP: the IRQ is only serviced after the instruction that follows CLI
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0x58, 0xea, 0xea], I_SET, Some(0), None, 3);
+        assert_eq!(executed, vec![0x0200, 0x0201, 0x0600]);
+
+        // SEI ; NOP: an IRQ that arrives during SEI is still serviced after it, with I set in the pushed status
+        let (executed, cpu) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0x78, 0xea], I_CLEAR, Some(1), None, 2);
+        assert_eq!(executed, vec![0x0200, 0x0600]);
+        assert_eq!(cpu.peek(0x01FB), 0b0010_0100);
+
+        // CLI ; SEI ; NOP: the IRQ is serviced once, right after SEI
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0x58, 0x78, 0xea], I_SET, Some(0), None, 3);
+        assert_eq!(executed, vec![0x0200, 0x0201, 0x0600]);
+
+        // PHA (status 0x00, with I clear) ; PLP ; NOP ; NOP: the I flag pulled by PLP is also delayed
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0x48, 0x28, 0xea, 0xea], I_SET, Some(0), None, 4);
+        assert_eq!(executed, vec![0x0200, 0x0201, 0x0202, 0x0600]);
+
+        // RTI restores the I flag before the poll: the IRQ is serviced right after it
+        // 0200: PHA ; PHA ; PHA (return address 0x0000, status 0x00) ; RTI
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0x48, 0x48, 0x48, 0x40], I_SET, Some(0), None, 5);
+        assert_eq!(executed, vec![0x0200, 0x0201, 0x0202, 0x0203, 0x0600]);
+    }
+
+    #[test]
+    fn test_branch_delays_irq() {
+        const Z_SET: u8 = 0b0010_0010;
+        const Z_CLEAR: u8 = 0b0010_0000;
+
+        // The IRQ is asserted during the second cycle. A 3-cycle instruction (LDA $10) polls after it.
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0xa5, 0x10, 0xea], Z_SET, Some(2), None, 2);
+        assert_eq!(executed, vec![0x0200, 0x0600]);
+
+        // A taken branch (BEQ +0) does not poll in its third cycle, so the IRQ waits for the next instruction
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0xf0, 0x00, 0xea], Z_SET, Some(2), None, 3);
+        assert_eq!(executed, vec![0x0200, 0x0202, 0x0600]);
+
+        // Not taken: the branch takes 2 cycles, and the IRQ arrives too late for its poll
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0xf0, 0x00, 0xea], Z_CLEAR, Some(2), None, 3);
+        assert_eq!(executed, vec![0x0200, 0x0202, 0x0600]);
+
+        // Taken across a page (to 0x0300): the fourth cycle polls again
+        let mut program = vec![0xea; 0x100];
+        program[0] = 0x4c; // JMP $02FD
+        program[1] = 0xfd;
+        program[2] = 0x02;
+        program[0xfd] = 0xf0; // BEQ +1
+        program[0xfe] = 0x01;
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &program, Z_SET, Some(5), None, 3);
+        assert_eq!(executed, vec![0x0200, 0x02FD, 0x0600]);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk_and_irq() {
+        const I_SET: u8 = 0b0010_0100;
+        const I_CLEAR: u8 = 0b0010_0000;
+
+        // BRK ; padding. An NMI in the first 4 cycles of BRK makes it jump to the NMI vector, with B set in the
+        // pushed status, and the NMI is not serviced again.
+        for nmi_from in 1..=4 {
+            let (executed, cpu) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0x00, 0x00], I_SET, None, Some(nmi_from), 3);
+            assert_eq!(executed, vec![0x0200, 0x0500, 0x0501], "NMI from cycle {}", nmi_from);
+            assert_eq!(cpu.peek(0x01FB), 0b0011_0100);
+        }
+        // Later, BRK completes normally and the NMI is serviced after the first instruction of the handler
+        for nmi_from in 5..=7 {
+            let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0x00, 0x00], I_SET, None, Some(nmi_from), 3);
+            assert_eq!(executed, vec![0x0200, 0x0600, 0x0500], "NMI from cycle {}", nmi_from);
+        }
+        // The 65C02 does not have the bug
+        let (executed, _) = run_interrupt_scenario(CpuModel::Wdc65C02, &[0x00, 0x00], I_SET, None, Some(2), 3);
+        assert_eq!(executed, vec![0x0200, 0x0600, 0x0500]);
+
+        // NOP, followed by the IRQ sequence in cycles 3 to 9. An NMI that arrives before its fifth cycle takes over
+        // the sequence, with B clear in the pushed status.
+        let (executed, cpu) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0xea, 0xea], I_CLEAR, Some(0), Some(6), 3);
+        assert_eq!(executed, vec![0x0200, 0x0500, 0x0501]);
+        assert_eq!(cpu.peek(0x01FB), 0b0010_0000);
+        let (executed, _) = run_interrupt_scenario(CpuModel::Ricoh2A03, &[0xea, 0xea], I_CLEAR, Some(0), Some(7), 3);
+        assert_eq!(executed, vec![0x0200, 0x0600, 0x0500]);
+    }
+
+    // Memory with a status register at 0x2002 whose bit 7 is cleared when it is read, like PPUSTATUS
+    struct ClearOnRead {
+        ram: Ram,
+        status: u8
+    }
+
+    impl Mem for ClearOnRead {
+        fn mem_read(&mut self, address: u16) -> u8 {
+            let value = self.peek(address);
+            if address == 0x2002 {
+                self.status &= 0x7F;
+            }
+            value
+        }
+
+        fn mem_write(&mut self, address: u16, data: u8) {
+            self.ram.mem_write(address, data);
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            if address == 0x2002 { self.status } else { self.ram.peek(address) }
+        }
+    }
+
+    #[test]
+    fn test_reads_with_side_effects() {
+        // LDA $2002 ; LDX $2002
+        let mut ram = Ram::new();
+        ram.load(0x0200, &[0xad, 0x02, 0x20, 0xae, 0x02, 0x20]);
+        let mut cpu = CPU::new(ClearOnRead { ram, status: 0x80 });
+        cpu.program_counter = 0x0200;
+
+        // Peeking does not acknowledge the flag, while the read by the CPU does
+        assert_eq!(cpu.peek(0x2002), 0x80);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_a, 0x80);
+        assert_eq!(cpu.register_x, 0x00);
+    }
 }
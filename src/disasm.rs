@@ -0,0 +1,259 @@
+use crate::cartridge::Rom;
+use crate::cpu::AddressingMode;
+use crate::cpu::CpuModel;
+use crate::opcodes;
+use crate::opcodes::OpCode;
+use std::fmt;
+
+/*
+    Disassembler for 6502 machine code. Instructions are decoded with the metadata of the opcodes module, and
+    formatted with the syntax of the ca65 and asm6 assemblers, so that a listing can be assembled again.
+*/
+
+const PRG_ROM_BANK_SIZE: usize = 0x4000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,              // address of the opcode
+    pub bytes: Vec<u8>,         // opcode and operand (fewer than len, if the code ends before the instruction)
+    pub len: u8,                // length of the instruction in bytes
+    pub mnemonic: &'static str, // as in the opcodes module, where unofficial opcodes are prefixed with '*'
+    pub mode: AddressingMode,
+    pub operand: Option<u16>,   // value of the operand bytes, in little-endian order
+    pub official: bool,
+    pub target: Option<u16>     // destination of branches, jumps and subroutine calls
+}
+
+/*
+    Decode the instruction at the beginning of the code, which is located at the given address.
+    Returns None if the code is empty.
+*/
+pub fn decode(code: &[u8], addr: u16, model: CpuModel) -> Option<Instruction> {
+    let opcode = &opcodes::table(model)[*code.first()? as usize];
+    let len = (opcode.len as usize).min(code.len());
+    let bytes = code[..len].to_vec();
+
+    let operand = match (opcode.len, len) {
+        (2, 2) => Some(bytes[1] as u16),
+        (3, 3) => Some((bytes[2] as u16) << 8 | bytes[1] as u16),
+        _ => None
+    };
+    let next = addr.wrapping_add(opcode.len as u16);
+
+    Some(Instruction {
+        addr,
+        bytes,
+        len: opcode.len,
+        mnemonic: opcode.mnemonic,
+        mode: opcode.mode,
+        operand,
+        official: opcode.official,
+        target: operand.and_then(|operand| jump_target(opcode, operand, next))
+    })
+}
+
+/*
+    Destination of a branch or an absolute jump. Indirect jumps depend on the contents of memory at runtime.
+*/
+fn jump_target(opcode: &OpCode, operand: u16, next: u16) -> Option<u16> {
+    match (opcode.mode, opcode.len) {
+        // Branches, with a signed offset from the next instruction
+        (AddressingMode::NoneAddressing, 2) => Some(next.wrapping_add(operand as u8 as i8 as u16)),
+        // BBR and BBS (65C02): the offset follows the zero page address
+        (AddressingMode::ZeroPage_Relative, _) => Some(next.wrapping_add((operand >> 8) as u8 as i8 as u16)),
+        // JMP and JSR absolute
+        (AddressingMode::NoneAddressing, 3) if opcode.code == 0x4C || opcode.code == 0x20 => Some(operand),
+        _ => None
+    }
+}
+
+/*
+    Decode all the instructions in the code, which starts at the origin.
+*/
+pub fn disassemble(code: &[u8], origin: u16, model: CpuModel) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while let Some(instruction) = decode(&code[offset..], origin.wrapping_add(offset as u16), model) {
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/*
+    Disassemble a 16 KiB bank of PRG-ROM. The last bank is placed at 0xC000, where the vectors are, and the others at
+    0x8000, which is where NROM and most mappers switch them in.
+*/
+pub fn disassemble_prg_bank(rom: &Rom, bank: usize, model: CpuModel) -> Result<Vec<Instruction>, String> {
+    let banks = rom.prg_rom.len() / PRG_ROM_BANK_SIZE;
+    if bank >= banks {
+        return Err(format!("PRG-ROM bank {} does not exist (the ROM has {})", bank, banks));
+    }
+    let origin = if bank == banks - 1 { 0xC000 } else { 0x8000 };
+    let code = &rom.prg_rom[bank * PRG_ROM_BANK_SIZE..(bank + 1) * PRG_ROM_BANK_SIZE];
+    Ok(disassemble(code, origin, model))
+}
+
+impl Instruction {
+    /*
+        Operand in assembler syntax. Branches and jumps show the address of the target.
+    */
+    pub fn operand_syntax(&self) -> String {
+        let operand = match self.operand {
+            Some(operand) => operand,
+            None => {
+                // Shifts, rotations and the 65C02 increments have a form that operates on the accumulator
+                let accumulator = ["ASL", "LSR", "ROL", "ROR", "INC", "DEC"].contains(&self.mnemonic);
+                return if accumulator && self.bytes.len() == 1 { String::from("A") } else { String::new() };
+            }
+        };
+
+        match self.mode {
+            AddressingMode::Immediate => format!("#${:02X}", operand),
+            AddressingMode::ZeroPage => format!("${:02X}", operand),
+            AddressingMode::ZeroPage_X => format!("${:02X},X", operand),
+            AddressingMode::ZeroPage_Y => format!("${:02X},Y", operand),
+            AddressingMode::Absolute => format!("${:04X}", operand),
+            AddressingMode::Absolute_X => format!("${:04X},X", operand),
+            AddressingMode::Absolute_Y => format!("${:04X},Y", operand),
+            AddressingMode::Indirect_X => format!("(${:02X},X)", operand),
+            AddressingMode::Indirect_Y => format!("(${:02X}),Y", operand),
+            AddressingMode::ZeroPage_Indirect => format!("(${:02X})", operand),
+            AddressingMode::ZeroPage_Relative => format!("${:02X},${:04X}", operand & 0xFF, self.target.unwrap_or(0)),
+            AddressingMode::NoneAddressing => match (self.bytes[0], self.target) {
+                (_, Some(target)) => format!("${:04X}", target),
+                (0x7C, None) => format!("(${:04X},X)", operand),
+                _ => format!("(${:04X})", operand)
+            }
+        }
+    }
+
+    /*
+        True if assembling the syntax of the instruction gives back the same bytes. It does not, for unofficial
+        opcodes (which the assemblers do not accept, or encode differently), for absolute addresses in the zero page
+        (which they encode with the shorter zero page modes), and for instructions cut by the end of the code.
+    */
+    pub fn is_assemblable(&self) -> bool {
+        let complete = self.bytes.len() == self.len as usize;
+        let zero_page_address = match self.mode {
+            AddressingMode::Absolute | AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+                self.operand.is_some_and(|operand| operand < 0x100)
+            }
+            _ => false
+        };
+        self.official && complete && !zero_page_address
+    }
+}
+
+/*
+    Assembler source of the instruction. When it cannot be expressed as an instruction, its bytes are given as data,
+    followed by the instruction as a comment.
+*/
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let syntax = format!("{} {}", self.mnemonic, self.operand_syntax());
+        if self.is_assemblable() {
+            write!(f, "{}", syntax.trim_end())
+        } else {
+            let bytes = self.bytes.iter().map(|byte| format!("${:02X}", byte)).collect::<Vec<String>>();
+            write!(f, ".byte {} ; {}", bytes.join(", "), syntax.trim_end())
+        }
+    }
+}
+
+/*
+    Source listing of the instructions, starting at the address of the first one. Each line is commented with the
+    address and the bytes of the instruction.
+*/
+pub fn listing(instructions: &[Instruction]) -> String {
+    let mut lines = vec![];
+    if let Some(first) = instructions.first() {
+        lines.push(format!(".org ${:04X}", first.addr));
+    }
+    for instruction in instructions {
+        let bytes = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>();
+        lines.push(format!("    {:<32} ; {:04X}  {}", instruction.to_string(), instruction.addr, bytes.join(" ")));
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    fn syntax(code: &[u8], addr: u16, model: CpuModel) -> Vec<String> {
+        disassemble(code, addr, model).iter().map(|instruction| instruction.to_string()).collect()
+    }
+
+    #[test]
+    fn test_decode_instruction() {
+        // BNE -4
+        let instruction = decode(&[0xd0, 0xfc, 0xea], 0xC010, CpuModel::Ricoh2A03).unwrap();
+        assert_eq!(instruction, Instruction {
+            addr: 0xC010,
+            bytes: vec![0xd0, 0xfc],
+            len: 2,
+            mnemonic: "BNE",
+            mode: AddressingMode::NoneAddressing,
+            operand: Some(0xfc),
+            official: true,
+            target: Some(0xC00E)
+        });
+        assert_eq!(decode(&[], 0xC000, CpuModel::Ricoh2A03), None);
+    }
+
+    #[test]
+    fn test_format_addressing_modes() {
+        let code = [
+            0xa9, 0xc0,       // LDA #$C0
+            0x0a,             // ASL A
+            0xb5, 0x10,       // LDA $10,X
+            0xb6, 0x10,       // LDX $10,Y
+            0xbd, 0x34, 0x12, // LDA $1234,X
+            0x61, 0x20,       // ADC ($20,X)
+            0x71, 0x20,       // ADC ($20),Y
+            0x20, 0x00, 0x90, // JSR $9000
+            0x6c, 0xfe, 0x02, // JMP ($02FE)
+            0x10, 0x00,       // BPL $8016
+        ];
+        assert_eq!(syntax(&code, 0x8000, CpuModel::Ricoh2A03), vec![
+            "LDA #$C0", "ASL A", "LDA $10,X", "LDX $10,Y", "LDA $1234,X", "ADC ($20,X)", "ADC ($20),Y",
+            "JSR $9000", "JMP ($02FE)", "BPL $8016",
+        ]);
+
+        // LDA ($20) ; INC A ; JMP ($1234,X) ; BBS7 $21,$0200
+        let code = [0xb2, 0x20, 0x1a, 0x7c, 0x34, 0x12, 0xff, 0x21, 0xf7];
+        assert_eq!(syntax(&code, 0x0200, CpuModel::Wdc65C02), vec![
+            "LDA ($20)", "INC A", "JMP ($1234,X)", "BBS7 $21,$0200",
+        ]);
+    }
+
+    #[test]
+    fn test_unassemblable_instructions_are_data() {
+        // *NOP $10 ; LDA $0010 (absolute) ; LDA with the operand cut
+        assert_eq!(syntax(&[0x04, 0x10, 0xad, 0x10, 0x00, 0xa9], 0x8000, CpuModel::Ricoh2A03), vec![
+            ".byte $04, $10 ; *NOP $10", ".byte $AD, $10, $00 ; LDA $0010", ".byte $A9 ; LDA",
+        ]);
+    }
+
+    #[test]
+    fn test_listing_of_prg_bank() {
+        let rom = test_rom(vec![0xa2, 0x01, 0xca]);
+        let instructions = disassemble_prg_bank(&rom, 0, CpuModel::Ricoh2A03).unwrap();
+        assert_eq!(instructions[0].addr, 0x8000);
+        assert_eq!(instructions.iter().map(|instruction| instruction.bytes.len()).sum::<usize>(), 0x4000);
+
+        let listing = listing(&instructions[..2]);
+        assert_eq!(listing, [
+            ".org $8000",
+            "    LDX #$01                         ; 8000  A2 01",
+            "    DEX                              ; 8002  CA",
+        ].join("\n") + "\n");
+
+        // The last bank holds the vectors
+        let instructions = disassemble_prg_bank(&rom, 1, CpuModel::Ricoh2A03).unwrap();
+        assert_eq!(instructions[0].addr, 0xC000);
+        assert!(disassemble_prg_bank(&rom, 2, CpuModel::Ricoh2A03).is_err());
+    }
+}
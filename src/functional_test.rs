@@ -0,0 +1,190 @@
+use crate::cpu::CpuModel;
+use crate::cpu::Mem;
+use crate::cpu::CPU;
+use crate::ram::Ram;
+
+/*
+    Runner for Klaus Dormann's 6502 test suite (https://github.com/Klaus2m5/6502_65C02_functional_tests).
+
+    The binaries are raw memory images that run from flat RAM. When a test fails, the program traps the CPU in an
+    infinite loop ("jump to self", either a JMP or a branch to its own address), so the address of the trap identifies
+    the failing test in the listing. When all the tests pass, the program traps at a known success address.
+*/
+
+// Defaults of the images assembled with the configuration distributed with the suite
+const FUNCTIONAL_TEST_SUCCESS: u16 = 0x3469;
+const INTERRUPT_TEST_SUCCESS: u16 = 0x06F5;
+const CODE_SEGMENT: u16 = 0x0400;
+const INTERRUPT_FEEDBACK_PORT: u16 = 0xBFFC;
+const MEMORY_SIZE: usize = 0x10000;
+
+// Bits of the feedback port that drive the interrupt lines, in the interrupt test
+const IRQ_BIT: u8 = 0b0000_0001;
+const NMI_BIT: u8 = 0b0000_0010;
+
+pub struct TestConfig {
+    pub origin: u16,             // address where the image is loaded
+    pub start: u16,              // initial value of the program counter
+    pub success: Option<u16>,    // address of the success trap (without it, any trap is reported as a failure)
+    pub max_cycles: u64,         // give up if no trap is reached after this many cycles
+    pub model: CpuModel,         // the generic images need decimal mode, which the NES 2A03 lacks
+    pub feedback_port: Option<u16> // I/O port whose bits drive the IRQ and NMI lines
+}
+
+impl TestConfig {
+    /*
+        Configuration for 6502_functional_test.bin: a full 64 KiB image, with the code starting at 0x0400.
+        The distributed image also tests decimal mode, so it runs on an NMOS 6502. To check the NES configuration,
+        assemble it with disable_decimal = 1 and use the 2A03 model.
+    */
+    pub fn functional() -> Self {
+        TestConfig {
+            origin: 0x0000,
+            start: CODE_SEGMENT,
+            success: Some(FUNCTIONAL_TEST_SUCCESS),
+            max_cycles: 200_000_000,
+            model: CpuModel::Nmos6502,
+            feedback_port: None
+        }
+    }
+
+    /*
+        Configuration for 6502_interrupt_test.bin, which triggers interrupts by writing to a feedback port.
+    */
+    pub fn interrupt() -> Self {
+        TestConfig {
+            origin: 0x0000,
+            start: CODE_SEGMENT,
+            success: Some(INTERRUPT_TEST_SUCCESS),
+            max_cycles: 10_000_000,
+            model: CpuModel::Nmos6502,
+            feedback_port: Some(INTERRUPT_FEEDBACK_PORT)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed { pc: u16, cycles: u64 },   // trapped at the success address
+    Trapped { pc: u16, cycles: u64 },  // trapped anywhere else: the test at this address failed
+    TimedOut { pc: u16, cycles: u64 }, // no trap was reached before the cycle limit
+}
+
+impl TestOutcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, TestOutcome::Passed { .. })
+    }
+}
+
+/*
+    Load the image at the origin of the configuration, and run it until the CPU traps in a jump to self.
+    Fails if the image does not fit in memory above the origin, or if the CPU stops with an error.
+*/
+pub fn run_test(image: &[u8], config: &TestConfig) -> Result<TestOutcome, String> {
+    if config.origin as usize + image.len() > MEMORY_SIZE {
+        return Err(format!(
+            "The image ({} bytes) does not fit in memory when loaded at {:04X}", image.len(), config.origin
+        ));
+    }
+    let mut ram = Ram::new();
+    ram.load(config.origin, image);
+    let mut cpu = CPU::with_model(ram, config.model);
+    cpu.program_counter = config.start;
+
+    let mut nmi_line = false;
+    loop {
+        let result = cpu.step().map_err(|error| error.to_string())?;
+
+        // The interrupt test asserts the lines by setting bits in the port. NMI is triggered by the rising edge.
+        if let Some(port) = config.feedback_port {
+            let feedback = cpu.bus.peek(port);
+            cpu.set_irq_line(feedback & IRQ_BIT != 0);
+            if feedback & NMI_BIT != 0 && !nmi_line {
+                cpu.trigger_nmi();
+            }
+            nmi_line = feedback & NMI_BIT != 0;
+        }
+
+        if result.interrupt.is_none() && result.pc_after == result.pc_before {
+            let pc = result.pc_after;
+            return Ok(if config.success == Some(pc) {
+                TestOutcome::Passed { pc, cycles: cpu.cycles }
+            } else {
+                TestOutcome::Trapped { pc, cycles: cpu.cycles }
+            });
+        }
+        if cpu.cycles >= config.max_cycles {
+            return Ok(TestOutcome::TimedOut { pc: cpu.program_counter, cycles: cpu.cycles });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(success: u16) -> TestConfig {
+        TestConfig {
+            origin: 0x0400,
+            start: 0x0400,
+            success: Some(success),
+            max_cycles: 1000,
+            model: CpuModel::Ricoh2A03,
+            feedback_port: Some(INTERRUPT_FEEDBACK_PORT)
+        }
+    }
+
+    #[test]
+    fn test_reports_success_and_failure_traps() {
+        // 0400: LDA #$01; CMP #$01; BNE 0409; JMP 0406 (success trap)
+        // 0409: BNE 0409 (failure trap)
+        let outcome = run_test(&[0xa9, 0x01, 0xc9, 0x01, 0xd0, 0x03, 0x4c, 0x06, 0x04, 0xd0, 0xfe], &config(0x0406));
+        assert_eq!(outcome, Ok(TestOutcome::Passed { pc: 0x0406, cycles: 2 + 2 + 2 + 3 }));
+
+        // Same, but the comparison fails
+        let outcome = run_test(&[0xa9, 0x01, 0xc9, 0x02, 0xd0, 0x03, 0x4c, 0x06, 0x04, 0xd0, 0xfe], &config(0x0406));
+        assert_eq!(outcome, Ok(TestOutcome::Trapped { pc: 0x0409, cycles: 2 + 2 + 3 + 3 }));
+    }
+
+    #[test]
+    fn test_image_must_fit_above_origin() {
+        let outcome = run_test(&[0xea; 0x10000], &config(0x0406));
+        assert_eq!(outcome, Err(String::from("The image (65536 bytes) does not fit in memory when loaded at 0400")));
+    }
+
+    #[test]
+    fn test_feedback_port_triggers_irq() {
+        // 0400: CLI; LDA #$01; STA $BFFC; NOP; JMP 0408 (failure trap)
+        // IRQ handler at 0x0500: LDA #$00; STA $BFFC; JMP 0505 (success trap)
+        let mut image = vec![0x58, 0xa9, 0x01, 0x8d, 0xfc, 0xbf, 0xea, 0xea, 0x4c, 0x08, 0x04];
+        image.resize(0x100, 0x00);
+        image.extend(&[0xa9, 0x00, 0x8d, 0xfc, 0xbf, 0x4c, 0x05, 0x05]);
+        image.resize(0xFFFE - 0x0400, 0x00);
+        image.extend(&[0x00, 0x05]);
+
+        let outcome = run_test(&image, &config(0x0505)).unwrap();
+        assert!(outcome.passed());
+    }
+
+    /*
+        Run the full functional test, if the binary has been placed in the test_roms directory:
+        cargo test --release -- --ignored klaus
+    */
+    #[test]
+    #[ignore]
+    fn test_klaus_functional_test() {
+        let image = std::fs::read("test_roms/6502_functional_test.bin")
+            .expect("Copy 6502_functional_test.bin from the test suite to test_roms");
+        let outcome = run_test(&image, &TestConfig::functional()).unwrap();
+        assert!(outcome.passed(), "{:?}", outcome);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_klaus_interrupt_test() {
+        let image = std::fs::read("test_roms/6502_interrupt_test.bin")
+            .expect("Copy 6502_interrupt_test.bin from the test suite to test_roms");
+        let outcome = run_test(&image, &TestConfig::interrupt()).unwrap();
+        assert!(outcome.passed(), "{:?}", outcome);
+    }
+}
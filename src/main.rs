@@ -3,16 +3,28 @@ pub mod opcodes;
 pub mod bus;
 pub mod cartridge;
 pub mod trace;
+pub mod ram;
+pub mod functional_test;
+pub mod single_step;
+pub mod disasm;
+pub mod assembler;
+pub mod trace_diff;
+pub mod trace_logger;
 
 // use crate::cpu::CPU;
 // use crate::cpu::Mem;
 use cpu::Mem;
 use cpu::CPU;
+use cpu::CpuModel;
 use bus::Bus;
 use cartridge::Rom;
 use trace::trace;
+use trace::Template;
+use trace::TraceFormat;
+use trace_diff::Field;
+use opcodes::Category;
+use trace_logger::TraceLogger;
 
-use rand::Rng;
 
 use sdl2::event::Event;
 use sdl2::EventPump;
@@ -20,9 +32,6 @@ use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
 
-#[macro_use]
-extern crate lazy_static;
-
 #[macro_use]
 extern crate bitflags;
 
@@ -81,7 +90,7 @@ fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     let mut update = false;
     // The state of the screen is in the memory range [0x0200, 0x0600]
     for i in 0x0200..0x0600 {
-        let color_idx = cpu.mem_read(i as u16);
+        let color_idx = cpu.peek(i as u16);
         let (b1, b2, b3) = color(color_idx).rgb();
         if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
             frame[frame_idx] = b1;
@@ -94,7 +103,139 @@ fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     update
 }
 
+/*
+    Commands that run without the frontend:
+        disasm <file.nes> [bank]    Disassemble a 16 KiB bank of PRG-ROM (the first one by default)
+        tracediff <left> <right> [--ignore field,...]
+                                    Compare two trace logs and report the first line that differs. The fields
+                                    are PC, bytes, disasm, A, X, Y, P, SP, PPU and CYC.
+        trace <file.nes> <log> [--format format] [--pc start-end] [--bank n] [--class category,...] [--last n]
+              [--break addr,...]
+                                    Run the ROM from its reset vector, writing the trace to the log. The format is
+                                    nintendulator (by default), mesen, fceux, or a template like "{PC} {DISASM}".
+                                    The categories are implied, read, write, rmw, branch and jump (--category is
+                                    the same option). With --last, only the last n instructions are written, when
+                                    the program stops at a breakpoint, with an error, or with a panic of the
+                                    emulator. Addresses are in hexadecimal.
+*/
+fn run_command(args: &[String]) -> Result<(), String> {
+    match args[0].as_str() {
+        "disasm" => {
+            let path = args.get(1).ok_or("Usage: disasm <file.nes> [bank]")?;
+            let bank = match args.get(2) {
+                Some(bank) => bank.parse::<usize>().map_err(|_| format!("Invalid bank: {}", bank))?,
+                None => 0
+            };
+            let rom_bytes = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+            let rom = Rom::new(&rom_bytes)?;
+            let instructions = disasm::disassemble_prg_bank(&rom, bank, CpuModel::Ricoh2A03)?;
+            print!("{}", disasm::listing(&instructions));
+            Ok(())
+        }
+        "tracediff" => {
+            let usage = "Usage: tracediff <left> <right> [--ignore field,...]";
+            let (left_path, right_path) = match (args.get(1), args.get(2)) {
+                (Some(left_path), Some(right_path)) => (left_path, right_path),
+                _ => return Err(String::from(usage))
+            };
+            let ignored = match (args.get(3).map(|arg| arg.as_str()), args.get(4)) {
+                (None, _) => vec![],
+                (Some("--ignore"), Some(fields)) => fields.split(',')
+                    .map(|name| Field::from_name(name.trim()).ok_or(format!("Unknown field: {}", name)))
+                    .collect::<Result<Vec<Field>, String>>()?,
+                _ => return Err(String::from(usage))
+            };
+
+            let read = |path: &String| std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error));
+            match trace_diff::first_mismatch(&read(left_path)?, &read(right_path)?, &ignored) {
+                Some(mismatch) => Err(mismatch.to_string()),
+                None => {
+                    println!("The traces match");
+                    Ok(())
+                }
+            }
+        }
+        "trace" => {
+            let usage = "Usage: trace <file.nes> <log> [--format format] [--pc start-end] [--bank n] \
+                [--class category,...] [--last n] [--break addr,...]";
+            let (rom_path, log_path) = match (args.get(1), args.get(2)) {
+                (Some(rom_path), Some(log_path)) => (rom_path, log_path),
+                _ => return Err(String::from(usage))
+            };
+            let rom_bytes = std::fs::read(rom_path).map_err(|error| format!("{}: {}", rom_path, error))?;
+            let rom = Rom::new(&rom_bytes)?;
+
+            let address = |value: &str| {
+                u16::from_str_radix(value.trim_start_matches('$'), 16).map_err(|_| format!("Invalid address: {}", value))
+            };
+            let number = |value: &str| value.parse::<usize>().map_err(|_| format!("Invalid number: {}", value));
+            let mut format = TraceFormat::Nintendulator;
+            let (mut pc_range, mut bank, mut last, mut breakpoints) = (None, None, None, vec![]);
+            let mut categories = vec![];
+            for option in args[3..].chunks(2) {
+                let value = option.get(1).ok_or(usage)?;
+                match option[0].as_str() {
+                    "--format" => format = match value.to_lowercase().as_str() {
+                        "nintendulator" => TraceFormat::Nintendulator,
+                        "mesen" => TraceFormat::Mesen,
+                        "fceux" => TraceFormat::Fceux,
+                        _ => TraceFormat::Template(Template::parse(value)?)
+                    },
+                    "--pc" => {
+                        let (start, end) = value.split_once('-').ok_or(format!("Invalid range: {}", value))?;
+                        pc_range = Some(address(start)?..=address(end)?);
+                    }
+                    "--bank" => bank = Some(number(value)?),
+                    "--class" | "--category" => categories = value.split(',')
+                        .map(|name| Category::from_name(name.trim()).ok_or(format!("Unknown category: {}", name)))
+                        .collect::<Result<Vec<Category>, String>>()?,
+                    "--last" => last = Some(number(value)?),
+                    "--break" => breakpoints = value.split(',').map(address).collect::<Result<Vec<u16>, String>>()?,
+                    _ => return Err(String::from(usage))
+                }
+            }
+
+            let file = std::fs::File::create(log_path).map_err(|error| format!("{}: {}", log_path, error))?;
+            let writer = std::io::BufWriter::new(file);
+            let mut logger = match last {
+                Some(capacity) => TraceLogger::with_ring_buffer(writer, format, capacity),
+                None => TraceLogger::new(writer, format)
+            };
+            logger.pc_range = pc_range;
+            logger.bank = bank;
+            logger.categories = categories;
+
+            // The trace kept by the ring buffer is most useful when the emulator panics, so the panic is caught to
+            // dump it before failing
+            let mut cpu = CPU::new(Bus::new(rom));
+            cpu.reset();
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| logger.run(&mut cpu, &breakpoints)));
+            match result {
+                Ok(stop) => {
+                    let stop = stop.map_err(|error| format!("{}: {}", log_path, error))?;
+                    println!("{}", stop);
+                    Ok(())
+                }
+                Err(_) => {
+                    logger.dump().map_err(|error| format!("{}: {}", log_path, error))?;
+                    Err(format!("The emulator panicked, the trace is in {}", log_path))
+                }
+            }
+        }
+        command => Err(format!("Unknown command: {}", command))
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(error) = run_command(&args) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // // Initialize SDL2
     // let sdl_context = sdl2::init().unwrap();
     // let video_subsystem = sdl_context.video().unwrap();
@@ -141,15 +282,20 @@ fn main() {
     // });
 
     // Run the test rom and print the trace
-    let rom_bytes: Vec<u8> = std::fs::read("../roms/nestest.nes").unwrap();
+    let rom_bytes: Vec<u8> = std::fs::read("test_roms/nestest.nes").unwrap();
     let rom = Rom::new(&rom_bytes).unwrap();
 
     let bus = Bus::new(rom);
     let mut cpu = CPU::new(bus);
     cpu.reset();
     cpu.program_counter = 0xC000;
+    // Stop when the test jumps to an empty region of memory after finishing
+    cpu.halt_opcode = Some(0x00);
 
-    cpu.run_with_callback(move |cpu| {
+    let result = cpu.run_with_callback(move |cpu| {
         println!("{}", trace(cpu));
     });
+    if let Err(error) = result {
+        println!("{}", error);
+    }
 }
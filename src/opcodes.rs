@@ -1,356 +1,789 @@
 use crate::cpu::AddressingMode;
-use std::collections::HashMap;
+use crate::cpu::CpuFlags;
+use crate::cpu::CpuModel;
+
+/*
+    How an instruction accesses memory, or changes the flow of the program.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Implied,         // no operand in memory: registers, flags, the stack, and the operations on the accumulator
+    Read,            // reads its operand (the immediate ones included)
+    Write,           // writes its operand
+    ReadModifyWrite, // reads its operand and writes the result back
+    Branch,          // conditional branches, which take +1 cycle if taken and +1 more if to a different page
+    Jump             // unconditional changes of the program counter: jumps, calls, returns and BRK
+}
+
+impl Category {
+    /*
+        Category with the given name, in any case. The read-modify-write instructions can also be given as "rmw".
+    */
+    pub fn from_name(name: &str) -> Option<Category> {
+        match name.to_lowercase().as_str() {
+            "implied" => Some(Category::Implied),
+            "read" => Some(Category::Read),
+            "write" => Some(Category::Write),
+            "readmodifywrite" | "rmw" => Some(Category::ReadModifyWrite),
+            "branch" => Some(Category::Branch),
+            "jump" => Some(Category::Jump),
+            _ => None
+        }
+    }
+}
 
+#[derive(Debug, Clone, Copy)]
 pub struct OpCode {
     pub code: u8,
     pub mnemonic: &'static str,
     pub len: u8,
     pub cycles: u8,
-    pub mode: AddressingMode
+    pub mode: AddressingMode,
+    pub category: Category,
+    pub official: bool,
+    pub flags: CpuFlags,         // flags changed by the instruction (the 65C02 also clears D when BRK is taken)
+    pub page_cross_penalty: bool // +1 cycle when the indexed address is on a different page than the base address
 }
 
-impl OpCode {
-    fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
-        OpCode {
-            code: code,
-            mnemonic: mnemonic,
-            len: len,
-            cycles: cycles,
-            mode: mode
-        }
+/*
+    Flags given by their letters, as in "NV-BDIZC".
+*/
+const fn parse_flags(letters: &str) -> CpuFlags {
+    let letters = letters.as_bytes();
+    let mut bits = 0;
+    let mut i = 0;
+    while i < letters.len() {
+        bits |= match letters[i] {
+            b'N' => CpuFlags::NEGATIVE.bits(),
+            b'V' => CpuFlags::OVERFLOW.bits(),
+            b'D' => CpuFlags::DECIMAL_MODE.bits(),
+            b'I' => CpuFlags::INTERRUPT_DISABLE.bits(),
+            b'Z' => CpuFlags::ZERO.bits(),
+            b'C' => CpuFlags::CARRY.bits(),
+            _ => panic!("Unknown flag")
+        };
+        i += 1;
     }
+    CpuFlags::from_bits_truncate(bits)
+}
+
+/*
+    Category and affected flags of the instruction executed by each handler of the CPU.
+*/
+macro_rules! instruction_metadata {
+    (adc) => { (Category::Read, "NVZC") };
+    (sbc) => { (Category::Read, "NVZC") };
+    (and) => { (Category::Read, "NZ") };
+    (eor) => { (Category::Read, "NZ") };
+    (ora) => { (Category::Read, "NZ") };
+    (cmp) => { (Category::Read, "NZC") };
+    (cpx) => { (Category::Read, "NZC") };
+    (cpy) => { (Category::Read, "NZC") };
+    (bit) => { (Category::Read, "NVZ") };
+    (bit_immediate) => { (Category::Read, "Z") };
+    (lda) => { (Category::Read, "NZ") };
+    (ldx) => { (Category::Read, "NZ") };
+    (ldy) => { (Category::Read, "NZ") };
+    (nop_read) => { (Category::Read, "") };
+    (nop_long) => { (Category::Read, "") };
+
+    (sta) => { (Category::Write, "") };
+    (stx) => { (Category::Write, "") };
+    (sty) => { (Category::Write, "") };
+    (stz) => { (Category::Write, "") };
+
+    (asl) => { (Category::ReadModifyWrite, "NZC") };
+    (lsr) => { (Category::ReadModifyWrite, "NZC") };
+    (rol) => { (Category::ReadModifyWrite, "NZC") };
+    (ror) => { (Category::ReadModifyWrite, "NZC") };
+    (inc) => { (Category::ReadModifyWrite, "NZ") };
+    (dec) => { (Category::ReadModifyWrite, "NZ") };
+    (tsb) => { (Category::ReadModifyWrite, "Z") };
+    (trb) => { (Category::ReadModifyWrite, "Z") };
+    (rmb) => { (Category::ReadModifyWrite, "") };
+    (smb) => { (Category::ReadModifyWrite, "") };
+
+    (asl_accumulator) => { (Category::Implied, "NZC") };
+    (lsr_accumulator) => { (Category::Implied, "NZC") };
+    (rol_accumulator) => { (Category::Implied, "NZC") };
+    (ror_accumulator) => { (Category::Implied, "NZC") };
+    (inc_accumulator) => { (Category::Implied, "NZ") };
+    (dec_accumulator) => { (Category::Implied, "NZ") };
+    (inx) => { (Category::Implied, "NZ") };
+    (iny) => { (Category::Implied, "NZ") };
+    (dex) => { (Category::Implied, "NZ") };
+    (dey) => { (Category::Implied, "NZ") };
+    (tax) => { (Category::Implied, "NZ") };
+    (tay) => { (Category::Implied, "NZ") };
+    (tsx) => { (Category::Implied, "NZ") };
+    (txa) => { (Category::Implied, "NZ") };
+    (tya) => { (Category::Implied, "NZ") };
+    (txs) => { (Category::Implied, "") };
+    (clc) => { (Category::Implied, "C") };
+    (sec) => { (Category::Implied, "C") };
+    (cld) => { (Category::Implied, "D") };
+    (sed) => { (Category::Implied, "D") };
+    (cli) => { (Category::Implied, "I") };
+    (sei) => { (Category::Implied, "I") };
+    (clv) => { (Category::Implied, "V") };
+    (pha) => { (Category::Implied, "") };
+    (php) => { (Category::Implied, "") };
+    (phx) => { (Category::Implied, "") };
+    (phy) => { (Category::Implied, "") };
+    (pla) => { (Category::Implied, "NZ") };
+    (plx) => { (Category::Implied, "NZ") };
+    (ply) => { (Category::Implied, "NZ") };
+    (plp) => { (Category::Implied, "NVDIZC") };
+    (nop) => { (Category::Implied, "") };
+    (wai) => { (Category::Implied, "") };
+    (stp) => { (Category::Implied, "") };
+    (jam) => { (Category::Implied, "") };
+
+    (bcc) => { (Category::Branch, "") };
+    (bcs) => { (Category::Branch, "") };
+    (beq) => { (Category::Branch, "") };
+    (bne) => { (Category::Branch, "") };
+    (bmi) => { (Category::Branch, "") };
+    (bpl) => { (Category::Branch, "") };
+    (bvc) => { (Category::Branch, "") };
+    (bvs) => { (Category::Branch, "") };
+    (bra) => { (Category::Branch, "") };
+    (bbr) => { (Category::Branch, "") };
+    (bbs) => { (Category::Branch, "") };
+
+    (jmp_absolute) => { (Category::Jump, "") };
+    (jmp_indirect) => { (Category::Jump, "") };
+    (jmp_indirect_fixed) => { (Category::Jump, "") };
+    (jmp_indirect_x) => { (Category::Jump, "") };
+    (jsr) => { (Category::Jump, "") };
+    (rts) => { (Category::Jump, "") };
+    (rti) => { (Category::Jump, "NVDIZC") };
+    (brk) => { (Category::Jump, "I") };
+
+    /* Unofficial opcodes */
+    (lax) => { (Category::Read, "NZ") };
+    (las) => { (Category::Read, "NZ") };
+    (anc) => { (Category::Read, "NZC") };
+    (alr) => { (Category::Read, "NZC") };
+    (arr) => { (Category::Read, "NVZC") };
+    (axs) => { (Category::Read, "NZC") };
+    (lxa) => { (Category::Read, "NZ") };
+    (xaa) => { (Category::Read, "NZ") };
+    (sax) => { (Category::Write, "") };
+    (ahx) => { (Category::Write, "") };
+    (shx) => { (Category::Write, "") };
+    (shy) => { (Category::Write, "") };
+    (tas) => { (Category::Write, "") };
+    (slo) => { (Category::ReadModifyWrite, "NZC") };
+    (rla) => { (Category::ReadModifyWrite, "NZC") };
+    (sre) => { (Category::ReadModifyWrite, "NZC") };
+    (rra) => { (Category::ReadModifyWrite, "NVZC") };
+    (dcp) => { (Category::ReadModifyWrite, "NZC") };
+    (isb) => { (Category::ReadModifyWrite, "NVZC") };
+}
+
+/*
+    Values of the last two columns of the instruction set below.
+*/
+macro_rules! official {
+    (Official) => { true };
+    (Unofficial) => { false };
+}
+
+macro_rules! page_cross_penalty {
+    (PageCross) => { true };
+    (NoPenalty) => { false };
 }
 
-lazy_static! {
-    pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
-        /* Break */
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
-
-        /* No operation */
-        OpCode::new(0xea, "NOP", 1, 2, AddressingMode::NoneAddressing),
-
-        /* Arithmetic */
-        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6D, "ADC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7D, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x79, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x71, "ADC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xFD, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xF9, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xF1, "SBC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2D, "AND", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3D, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x39, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x31, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4D, "EOR", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x5D, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x59, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x51, "EOR", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0D, "ORA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x1D, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x19, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x11, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        /* Shifts */
-        OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4E, "LSR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2E, "ROL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6E, "ROR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x7E, "ROR", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0xE6, "INC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xF6, "INC", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xEE, "INC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xFE, "INC", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xC8, "INY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xD6, "DEC", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xCE, "DEC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xDE, "DEC", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0xCA, "DEX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC5, "CMP", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xD5, "CMP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xCD, "CMP", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xDD, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xD9, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xC1, "CMP", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xD1, "CMP", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xC0, "CPY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC4, "CPY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xCC, "CPY", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0xE0, "CPX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE4, "CPX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xEC, "CPX", 3, 4, AddressingMode::Absolute),
-
-        /* Branching */
-        OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::NoneAddressing), //AddressingMode that acts as Immidiate
-        OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::NoneAddressing), //AddressingMode:Indirect with 6502 bug
-
-        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::NoneAddressing),
-        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xD0, "BNE", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x70, "BVS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x50, "BVC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x30, "BMI", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xF0, "BEQ", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xB0, "BCS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x90, "BCC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x10, "BPL", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
-
-        /* Stores and loads */
-        OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xAD, "LDA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBD, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xB9, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xA1, "LDA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xB1, "LDA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xA2, "LDX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA6, "LDX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB6, "LDX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0xAE, "LDX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBE, "LDX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-
-        OpCode::new(0xA0, "LDY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA4, "LDY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xAC, "LDY", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBC, "LDY", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-
-        OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x9D, "STA", 3, 5, AddressingMode::Absolute_X),
-        OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
-        OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0x8E, "STX", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8C, "STY", 3, 4, AddressingMode::Absolute),
-
-        /* Clear flags */
-        OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xA8, "TAY", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xBA, "TSX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
-
-        /* Stack */
-        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
-        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
-        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
-        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
-
-        /* Unofficial opcodes */
-                OpCode::new(0xc7, "*DCP", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xd7, "*DCP", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xCF, "*DCP", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xdF, "*DCP", 3, 7, AddressingMode::Absolute_X),
-        OpCode::new(0xdb, "*DCP", 3, 7, AddressingMode::Absolute_Y),
-        OpCode::new(0xd3, "*DCP", 2, 8, AddressingMode::Indirect_Y),
-        OpCode::new(0xc3, "*DCP", 2, 8, AddressingMode::Indirect_X),
-
-
-        OpCode::new(0x27, "*RLA", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x37, "*RLA", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2F, "*RLA", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x3F, "*RLA", 3, 7, AddressingMode::Absolute_X),
-        OpCode::new(0x3b, "*RLA", 3, 7, AddressingMode::Absolute_Y),
-        OpCode::new(0x33, "*RLA", 2, 8, AddressingMode::Indirect_Y),
-        OpCode::new(0x23, "*RLA", 2, 8, AddressingMode::Indirect_X),
-
-        OpCode::new(0x07, "*SLO", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x17, "*SLO", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0F, "*SLO", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x1f, "*SLO", 3, 7, AddressingMode::Absolute_X),
-        OpCode::new(0x1b, "*SLO", 3, 7, AddressingMode::Absolute_Y),
-        OpCode::new(0x03, "*SLO", 2, 8, AddressingMode::Indirect_X),
-        OpCode::new(0x13, "*SLO", 2, 8, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x47, "*SRE", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x57, "*SRE", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4F, "*SRE", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x5f, "*SRE", 3, 7, AddressingMode::Absolute_X),
-        OpCode::new(0x5b, "*SRE", 3, 7, AddressingMode::Absolute_Y),
-        OpCode::new(0x43, "*SRE", 2, 8, AddressingMode::Indirect_X),
-        OpCode::new(0x53, "*SRE", 2, 8, AddressingMode::Indirect_Y),
-
-
-        OpCode::new(0x80, "*NOP", 2,2, AddressingMode::Immediate),
-        OpCode::new(0x82, "*NOP", 2,2, AddressingMode::Immediate),
-        OpCode::new(0x89, "*NOP", 2,2, AddressingMode::Immediate),
-        OpCode::new(0xc2, "*NOP", 2,2, AddressingMode::Immediate),
-        OpCode::new(0xe2, "*NOP", 2,2, AddressingMode::Immediate),
-
-
-        OpCode::new(0xCB, "*AXS", 2,2, AddressingMode::Immediate),
-
-        OpCode::new(0x6B, "*ARR", 2,2, AddressingMode::Immediate),
-
-        OpCode::new(0xeb, "*SBC", 2,2, AddressingMode::Immediate),
-
-        OpCode::new(0x0b, "*ANC", 2,2, AddressingMode::Immediate),
-        OpCode::new(0x2b, "*ANC", 2,2, AddressingMode::Immediate),
-
-        OpCode::new(0x4b, "*ALR", 2,2, AddressingMode::Immediate),
-        // OpCode::new(0xCB, "IGN", 3,4 /* or 5*/, AddressingMode::Absolute_X),
-
-        OpCode::new(0x04, "*NOP", 2,3, AddressingMode::ZeroPage),
-        OpCode::new(0x44, "*NOP", 2,3, AddressingMode::ZeroPage),
-        OpCode::new(0x64, "*NOP", 2,3, AddressingMode::ZeroPage),
-        OpCode::new(0x14, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x34, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x54, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x74, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xd4, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xf4, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0c, "*NOP", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x1c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0x3c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0x5c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0x7c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0xdc, "*NOP", 3, 4 /* or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0xfc, "*NOP", 3, 4 /* or 5*/, AddressingMode::Absolute_X),
-
-        OpCode::new(0x67, "*RRA", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x77, "*RRA", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6f, "*RRA", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x7f, "*RRA", 3, 7, AddressingMode::Absolute_X),
-        OpCode::new(0x7b, "*RRA", 3, 7, AddressingMode::Absolute_Y),
-        OpCode::new(0x63, "*RRA", 2, 8, AddressingMode::Indirect_X),
-        OpCode::new(0x73, "*RRA", 2, 8, AddressingMode::Indirect_Y),
-
-
-        OpCode::new(0xe7, "*ISB", 2,5, AddressingMode::ZeroPage),
-        OpCode::new(0xf7, "*ISB", 2,6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xef, "*ISB", 3,6, AddressingMode::Absolute),
-        OpCode::new(0xff, "*ISB", 3,7, AddressingMode::Absolute_X),
-        OpCode::new(0xfb, "*ISB", 3,7, AddressingMode::Absolute_Y),
-        OpCode::new(0xe3, "*ISB", 2,8, AddressingMode::Indirect_X),
-        OpCode::new(0xf3, "*ISB", 2,8, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x02, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x12, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x22, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x32, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x42, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x52, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x62, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x72, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x92, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0xb2, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0xd2, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0xf2, "*NOP", 1,2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x1a, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x3a, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x5a, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0x7a, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0xda, "*NOP", 1,2, AddressingMode::NoneAddressing),
-        // OpCode::new(0xea, "NOP", 1,2, AddressingMode::NoneAddressing),
-        OpCode::new(0xfa, "*NOP", 1,2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xab, "*LXA", 2, 3, AddressingMode::Immediate), //todo: highly unstable and not used
-        //http://visual6502.org/wiki/index.php?title=6502_Opcode_8B_%28XAA,_ANE%29
-        OpCode::new(0x8b, "*XAA", 2, 3, AddressingMode::Immediate), //todo: highly unstable and not used
-        OpCode::new(0xbb, "*LAS", 3, 2, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpCode::new(0x9b, "*TAS", 3, 2, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpCode::new(0x93, "*AHX", 2, /* guess */ 8, AddressingMode::Indirect_Y), //todo: highly unstable and not used
-        OpCode::new(0x9f, "*AHX", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpCode::new(0x9e, "*SHX", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpCode::new(0x9c, "*SHY", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_X), //todo: highly unstable and not used
-
-        OpCode::new(0xa7, "*LAX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xb7, "*LAX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0xaf, "*LAX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbf, "*LAX", 3, 4, AddressingMode::Absolute_Y),
-        OpCode::new(0xa3, "*LAX", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xb3, "*LAX", 2, 5, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x87, "*SAX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x97, "*SAX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0x8f, "*SAX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x83, "*SAX", 2, 6, AddressingMode::Indirect_X),
-    ];
-
-    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map = HashMap::new();
-        for cpu_op in &*CPU_OPS_CODES {
-            map.insert(cpu_op.code, cpu_op);
+/*
+    Definition of the instruction set: for each opcode, its mnemonic, length in bytes, base number of cycles,
+    addressing mode, the method of the CPU that executes it, whether it is official, and whether it takes one more
+    cycle when the indexed address is on a different page. Only reads wait for the carry of the high byte: writes
+    and read-modify-write instructions always take the extra cycle, which is counted in their base cycles, except
+    the shifts and rotations of the 65C02 with abs,X.
+    This is the single source of truth for the instructions. The list is passed to the macro given as argument,
+    which is used to build both the metadata tables below and the dispatch tables in the CPU.
+
+    The documented instructions, common to every model, are listed here. Each model adds the opcodes that are
+    specific to it, which are given as the second argument.
+*/
+macro_rules! official_opcodes {
+    ($table:ident, [$($model_opcodes:tt)*]) => {
+        $table! {
+            /* Break */
+            (0x00, "BRK", 1, 7, NoneAddressing, brk, Official, NoPenalty),
+
+            /* No operation */
+            (0xea, "NOP", 1, 2, NoneAddressing, nop, Official, NoPenalty),
+
+            /* Arithmetic */
+            (0x69, "ADC", 2, 2, Immediate, adc, Official, NoPenalty),
+            (0x65, "ADC", 2, 3, ZeroPage, adc, Official, NoPenalty),
+            (0x75, "ADC", 2, 4, ZeroPage_X, adc, Official, NoPenalty),
+            (0x6D, "ADC", 3, 4, Absolute, adc, Official, NoPenalty),
+            (0x7D, "ADC", 3, 4, Absolute_X, adc, Official, PageCross),
+            (0x79, "ADC", 3, 4, Absolute_Y, adc, Official, PageCross),
+            (0x61, "ADC", 2, 6, Indirect_X, adc, Official, NoPenalty),
+            (0x71, "ADC", 2, 5, Indirect_Y, adc, Official, PageCross),
+
+            (0xE9, "SBC", 2, 2, Immediate, sbc, Official, NoPenalty),
+            (0xE5, "SBC", 2, 3, ZeroPage, sbc, Official, NoPenalty),
+            (0xF5, "SBC", 2, 4, ZeroPage_X, sbc, Official, NoPenalty),
+            (0xED, "SBC", 3, 4, Absolute, sbc, Official, NoPenalty),
+            (0xFD, "SBC", 3, 4, Absolute_X, sbc, Official, PageCross),
+            (0xF9, "SBC", 3, 4, Absolute_Y, sbc, Official, PageCross),
+            (0xE1, "SBC", 2, 6, Indirect_X, sbc, Official, NoPenalty),
+            (0xF1, "SBC", 2, 5, Indirect_Y, sbc, Official, PageCross),
+
+            (0x29, "AND", 2, 2, Immediate, and, Official, NoPenalty),
+            (0x25, "AND", 2, 3, ZeroPage, and, Official, NoPenalty),
+            (0x35, "AND", 2, 4, ZeroPage_X, and, Official, NoPenalty),
+            (0x2D, "AND", 3, 4, Absolute, and, Official, NoPenalty),
+            (0x3D, "AND", 3, 4, Absolute_X, and, Official, PageCross),
+            (0x39, "AND", 3, 4, Absolute_Y, and, Official, PageCross),
+            (0x21, "AND", 2, 6, Indirect_X, and, Official, NoPenalty),
+            (0x31, "AND", 2, 5, Indirect_Y, and, Official, PageCross),
+
+            (0x49, "EOR", 2, 2, Immediate, eor, Official, NoPenalty),
+            (0x45, "EOR", 2, 3, ZeroPage, eor, Official, NoPenalty),
+            (0x55, "EOR", 2, 4, ZeroPage_X, eor, Official, NoPenalty),
+            (0x4D, "EOR", 3, 4, Absolute, eor, Official, NoPenalty),
+            (0x5D, "EOR", 3, 4, Absolute_X, eor, Official, PageCross),
+            (0x59, "EOR", 3, 4, Absolute_Y, eor, Official, PageCross),
+            (0x41, "EOR", 2, 6, Indirect_X, eor, Official, NoPenalty),
+            (0x51, "EOR", 2, 5, Indirect_Y, eor, Official, PageCross),
+
+            (0x09, "ORA", 2, 2, Immediate, ora, Official, NoPenalty),
+            (0x05, "ORA", 2, 3, ZeroPage, ora, Official, NoPenalty),
+            (0x15, "ORA", 2, 4, ZeroPage_X, ora, Official, NoPenalty),
+            (0x0D, "ORA", 3, 4, Absolute, ora, Official, NoPenalty),
+            (0x1D, "ORA", 3, 4, Absolute_X, ora, Official, PageCross),
+            (0x19, "ORA", 3, 4, Absolute_Y, ora, Official, PageCross),
+            (0x01, "ORA", 2, 6, Indirect_X, ora, Official, NoPenalty),
+            (0x11, "ORA", 2, 5, Indirect_Y, ora, Official, PageCross),
+
+            /* Shifts */
+            (0x0A, "ASL", 1, 2, NoneAddressing, asl_accumulator, Official, NoPenalty),
+            (0x06, "ASL", 2, 5, ZeroPage, asl, Official, NoPenalty),
+            (0x16, "ASL", 2, 6, ZeroPage_X, asl, Official, NoPenalty),
+            (0x0E, "ASL", 3, 6, Absolute, asl, Official, NoPenalty),
+
+            (0x4A, "LSR", 1, 2, NoneAddressing, lsr_accumulator, Official, NoPenalty),
+            (0x46, "LSR", 2, 5, ZeroPage, lsr, Official, NoPenalty),
+            (0x56, "LSR", 2, 6, ZeroPage_X, lsr, Official, NoPenalty),
+            (0x4E, "LSR", 3, 6, Absolute, lsr, Official, NoPenalty),
+
+            (0x2A, "ROL", 1, 2, NoneAddressing, rol_accumulator, Official, NoPenalty),
+            (0x26, "ROL", 2, 5, ZeroPage, rol, Official, NoPenalty),
+            (0x36, "ROL", 2, 6, ZeroPage_X, rol, Official, NoPenalty),
+            (0x2E, "ROL", 3, 6, Absolute, rol, Official, NoPenalty),
+
+            (0x6A, "ROR", 1, 2, NoneAddressing, ror_accumulator, Official, NoPenalty),
+            (0x66, "ROR", 2, 5, ZeroPage, ror, Official, NoPenalty),
+            (0x76, "ROR", 2, 6, ZeroPage_X, ror, Official, NoPenalty),
+            (0x6E, "ROR", 3, 6, Absolute, ror, Official, NoPenalty),
+
+            (0xE6, "INC", 2, 5, ZeroPage, inc, Official, NoPenalty),
+            (0xF6, "INC", 2, 6, ZeroPage_X, inc, Official, NoPenalty),
+            (0xEE, "INC", 3, 6, Absolute, inc, Official, NoPenalty),
+            (0xFE, "INC", 3, 7, Absolute_X, inc, Official, NoPenalty),
+
+            (0xE8, "INX", 1, 2, NoneAddressing, inx, Official, NoPenalty),
+            (0xC8, "INY", 1, 2, NoneAddressing, iny, Official, NoPenalty),
+
+            (0xC6, "DEC", 2, 5, ZeroPage, dec, Official, NoPenalty),
+            (0xD6, "DEC", 2, 6, ZeroPage_X, dec, Official, NoPenalty),
+            (0xCE, "DEC", 3, 6, Absolute, dec, Official, NoPenalty),
+            (0xDE, "DEC", 3, 7, Absolute_X, dec, Official, NoPenalty),
+
+            (0xCA, "DEX", 1, 2, NoneAddressing, dex, Official, NoPenalty),
+            (0x88, "DEY", 1, 2, NoneAddressing, dey, Official, NoPenalty),
+
+            (0xC9, "CMP", 2, 2, Immediate, cmp, Official, NoPenalty),
+            (0xC5, "CMP", 2, 3, ZeroPage, cmp, Official, NoPenalty),
+            (0xD5, "CMP", 2, 4, ZeroPage_X, cmp, Official, NoPenalty),
+            (0xCD, "CMP", 3, 4, Absolute, cmp, Official, NoPenalty),
+            (0xDD, "CMP", 3, 4, Absolute_X, cmp, Official, PageCross),
+            (0xD9, "CMP", 3, 4, Absolute_Y, cmp, Official, PageCross),
+            (0xC1, "CMP", 2, 6, Indirect_X, cmp, Official, NoPenalty),
+            (0xD1, "CMP", 2, 5, Indirect_Y, cmp, Official, PageCross),
+
+            (0xC0, "CPY", 2, 2, Immediate, cpy, Official, NoPenalty),
+            (0xC4, "CPY", 2, 3, ZeroPage, cpy, Official, NoPenalty),
+            (0xCC, "CPY", 3, 4, Absolute, cpy, Official, NoPenalty),
+
+            (0xE0, "CPX", 2, 2, Immediate, cpx, Official, NoPenalty),
+            (0xE4, "CPX", 2, 3, ZeroPage, cpx, Official, NoPenalty),
+            (0xEC, "CPX", 3, 4, Absolute, cpx, Official, NoPenalty),
+
+            /* Branching */
+            //AddressingMode that acts as Immidiate
+            (0x4C, "JMP", 3, 3, NoneAddressing, jmp_absolute, Official, NoPenalty),
+
+            (0x20, "JSR", 3, 6, NoneAddressing, jsr, Official, NoPenalty),
+            (0x60, "RTS", 1, 6, NoneAddressing, rts, Official, NoPenalty),
+
+            (0x40, "RTI", 1, 6, NoneAddressing, rti, Official, NoPenalty),
+
+            (0xD0, "BNE", 2, 2, NoneAddressing, bne, Official, NoPenalty),
+            (0x70, "BVS", 2, 2, NoneAddressing, bvs, Official, NoPenalty),
+            (0x50, "BVC", 2, 2, NoneAddressing, bvc, Official, NoPenalty),
+            (0x30, "BMI", 2, 2, NoneAddressing, bmi, Official, NoPenalty),
+            (0xF0, "BEQ", 2, 2, NoneAddressing, beq, Official, NoPenalty),
+            (0xB0, "BCS", 2, 2, NoneAddressing, bcs, Official, NoPenalty),
+            (0x90, "BCC", 2, 2, NoneAddressing, bcc, Official, NoPenalty),
+            (0x10, "BPL", 2, 2, NoneAddressing, bpl, Official, NoPenalty),
+
+            (0x24, "BIT", 2, 3, ZeroPage, bit, Official, NoPenalty),
+            (0x2C, "BIT", 3, 4, Absolute, bit, Official, NoPenalty),
+
+            /* Stores and loads */
+            (0xA9, "LDA", 2, 2, Immediate, lda, Official, NoPenalty),
+            (0xA5, "LDA", 2, 3, ZeroPage, lda, Official, NoPenalty),
+            (0xB5, "LDA", 2, 4, ZeroPage_X, lda, Official, NoPenalty),
+            (0xAD, "LDA", 3, 4, Absolute, lda, Official, NoPenalty),
+            (0xBD, "LDA", 3, 4, Absolute_X, lda, Official, PageCross),
+            (0xB9, "LDA", 3, 4, Absolute_Y, lda, Official, PageCross),
+            (0xA1, "LDA", 2, 6, Indirect_X, lda, Official, NoPenalty),
+            (0xB1, "LDA", 2, 5, Indirect_Y, lda, Official, PageCross),
+
+            (0xA2, "LDX", 2, 2, Immediate, ldx, Official, NoPenalty),
+            (0xA6, "LDX", 2, 3, ZeroPage, ldx, Official, NoPenalty),
+            (0xB6, "LDX", 2, 4, ZeroPage_Y, ldx, Official, NoPenalty),
+            (0xAE, "LDX", 3, 4, Absolute, ldx, Official, NoPenalty),
+            (0xBE, "LDX", 3, 4, Absolute_Y, ldx, Official, PageCross),
+
+            (0xA0, "LDY", 2, 2, Immediate, ldy, Official, NoPenalty),
+            (0xA4, "LDY", 2, 3, ZeroPage, ldy, Official, NoPenalty),
+            (0xB4, "LDY", 2, 4, ZeroPage_X, ldy, Official, NoPenalty),
+            (0xAC, "LDY", 3, 4, Absolute, ldy, Official, NoPenalty),
+            (0xBC, "LDY", 3, 4, Absolute_X, ldy, Official, PageCross),
+
+            (0x85, "STA", 2, 3, ZeroPage, sta, Official, NoPenalty),
+            (0x95, "STA", 2, 4, ZeroPage_X, sta, Official, NoPenalty),
+            (0x8D, "STA", 3, 4, Absolute, sta, Official, NoPenalty),
+            (0x9D, "STA", 3, 5, Absolute_X, sta, Official, NoPenalty),
+            (0x99, "STA", 3, 5, Absolute_Y, sta, Official, NoPenalty),
+            (0x81, "STA", 2, 6, Indirect_X, sta, Official, NoPenalty),
+            (0x91, "STA", 2, 6, Indirect_Y, sta, Official, NoPenalty),
+
+            (0x86, "STX", 2, 3, ZeroPage, stx, Official, NoPenalty),
+            (0x96, "STX", 2, 4, ZeroPage_Y, stx, Official, NoPenalty),
+            (0x8E, "STX", 3, 4, Absolute, stx, Official, NoPenalty),
+
+            (0x84, "STY", 2, 3, ZeroPage, sty, Official, NoPenalty),
+            (0x94, "STY", 2, 4, ZeroPage_X, sty, Official, NoPenalty),
+            (0x8C, "STY", 3, 4, Absolute, sty, Official, NoPenalty),
+
+            /* Clear flags */
+            (0xD8, "CLD", 1, 2, NoneAddressing, cld, Official, NoPenalty),
+            (0x58, "CLI", 1, 2, NoneAddressing, cli, Official, NoPenalty),
+            (0xB8, "CLV", 1, 2, NoneAddressing, clv, Official, NoPenalty),
+            (0x18, "CLC", 1, 2, NoneAddressing, clc, Official, NoPenalty),
+            (0x38, "SEC", 1, 2, NoneAddressing, sec, Official, NoPenalty),
+            (0x78, "SEI", 1, 2, NoneAddressing, sei, Official, NoPenalty),
+            (0xF8, "SED", 1, 2, NoneAddressing, sed, Official, NoPenalty),
+
+            (0xAA, "TAX", 1, 2, NoneAddressing, tax, Official, NoPenalty),
+            (0xA8, "TAY", 1, 2, NoneAddressing, tay, Official, NoPenalty),
+            (0xBA, "TSX", 1, 2, NoneAddressing, tsx, Official, NoPenalty),
+            (0x8A, "TXA", 1, 2, NoneAddressing, txa, Official, NoPenalty),
+            (0x9A, "TXS", 1, 2, NoneAddressing, txs, Official, NoPenalty),
+            (0x98, "TYA", 1, 2, NoneAddressing, tya, Official, NoPenalty),
+
+            /* Stack */
+            (0x48, "PHA", 1, 3, NoneAddressing, pha, Official, NoPenalty),
+            (0x68, "PLA", 1, 4, NoneAddressing, pla, Official, NoPenalty),
+            (0x08, "PHP", 1, 3, NoneAddressing, php, Official, NoPenalty),
+            (0x28, "PLP", 1, 4, NoneAddressing, plp, Official, NoPenalty),
+
+            $($model_opcodes)*
         }
-        map
     };
 }
+
+pub(crate) use official_opcodes;
+
+/*
+    Instruction set of the NMOS 6502, which is also the one of the 2A03 of the NES: the official instructions, with
+    the JMP indirect bug, and the unofficial opcodes.
+*/
+macro_rules! cpu_opcodes {
+    ($table:ident) => {
+        $crate::opcodes::official_opcodes! { $table, [
+            //AddressingMode:Indirect with 6502 bug
+            (0x6C, "JMP", 3, 5, NoneAddressing, jmp_indirect, Official, NoPenalty),
+
+            (0x1E, "ASL", 3, 7, Absolute_X, asl, Official, NoPenalty),
+            (0x5E, "LSR", 3, 7, Absolute_X, lsr, Official, NoPenalty),
+            (0x3E, "ROL", 3, 7, Absolute_X, rol, Official, NoPenalty),
+            (0x7E, "ROR", 3, 7, Absolute_X, ror, Official, NoPenalty),
+
+            /* Unofficial opcodes */
+            (0xc7, "*DCP", 2, 5, ZeroPage, dcp, Unofficial, NoPenalty),
+            (0xd7, "*DCP", 2, 6, ZeroPage_X, dcp, Unofficial, NoPenalty),
+            (0xCF, "*DCP", 3, 6, Absolute, dcp, Unofficial, NoPenalty),
+            (0xDF, "*DCP", 3, 7, Absolute_X, dcp, Unofficial, NoPenalty),
+            (0xdb, "*DCP", 3, 7, Absolute_Y, dcp, Unofficial, NoPenalty),
+            (0xd3, "*DCP", 2, 8, Indirect_Y, dcp, Unofficial, NoPenalty),
+            (0xc3, "*DCP", 2, 8, Indirect_X, dcp, Unofficial, NoPenalty),
+
+
+            (0x27, "*RLA", 2, 5, ZeroPage, rla, Unofficial, NoPenalty),
+            (0x37, "*RLA", 2, 6, ZeroPage_X, rla, Unofficial, NoPenalty),
+            (0x2F, "*RLA", 3, 6, Absolute, rla, Unofficial, NoPenalty),
+            (0x3F, "*RLA", 3, 7, Absolute_X, rla, Unofficial, NoPenalty),
+            (0x3b, "*RLA", 3, 7, Absolute_Y, rla, Unofficial, NoPenalty),
+            (0x33, "*RLA", 2, 8, Indirect_Y, rla, Unofficial, NoPenalty),
+            (0x23, "*RLA", 2, 8, Indirect_X, rla, Unofficial, NoPenalty),
+
+            (0x07, "*SLO", 2, 5, ZeroPage, slo, Unofficial, NoPenalty),
+            (0x17, "*SLO", 2, 6, ZeroPage_X, slo, Unofficial, NoPenalty),
+            (0x0F, "*SLO", 3, 6, Absolute, slo, Unofficial, NoPenalty),
+            (0x1f, "*SLO", 3, 7, Absolute_X, slo, Unofficial, NoPenalty),
+            (0x1b, "*SLO", 3, 7, Absolute_Y, slo, Unofficial, NoPenalty),
+            (0x03, "*SLO", 2, 8, Indirect_X, slo, Unofficial, NoPenalty),
+            (0x13, "*SLO", 2, 8, Indirect_Y, slo, Unofficial, NoPenalty),
+
+            (0x47, "*SRE", 2, 5, ZeroPage, sre, Unofficial, NoPenalty),
+            (0x57, "*SRE", 2, 6, ZeroPage_X, sre, Unofficial, NoPenalty),
+            (0x4F, "*SRE", 3, 6, Absolute, sre, Unofficial, NoPenalty),
+            (0x5f, "*SRE", 3, 7, Absolute_X, sre, Unofficial, NoPenalty),
+            (0x5b, "*SRE", 3, 7, Absolute_Y, sre, Unofficial, NoPenalty),
+            (0x43, "*SRE", 2, 8, Indirect_X, sre, Unofficial, NoPenalty),
+            (0x53, "*SRE", 2, 8, Indirect_Y, sre, Unofficial, NoPenalty),
+
+
+            (0x80, "*NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0x82, "*NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0x89, "*NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0xc2, "*NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0xe2, "*NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+
+
+            (0xCB, "*AXS", 2, 2, Immediate, axs, Unofficial, NoPenalty),
+
+            (0x6B, "*ARR", 2, 2, Immediate, arr, Unofficial, NoPenalty),
+
+            (0xeb, "*SBC", 2, 2, Immediate, sbc, Unofficial, NoPenalty),
+
+            (0x0b, "*ANC", 2, 2, Immediate, anc, Unofficial, NoPenalty),
+            (0x2b, "*ANC", 2, 2, Immediate, anc, Unofficial, NoPenalty),
+
+            (0x4b, "*ALR", 2, 2, Immediate, alr, Unofficial, NoPenalty),
+            // OpCode::new(0xCB, "IGN", 3,4 /* or 5*/, AddressingMode::Absolute_X),
+
+            (0x04, "*NOP", 2, 3, ZeroPage, nop_read, Unofficial, NoPenalty),
+            (0x44, "*NOP", 2, 3, ZeroPage, nop_read, Unofficial, NoPenalty),
+            (0x64, "*NOP", 2, 3, ZeroPage, nop_read, Unofficial, NoPenalty),
+            (0x14, "*NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0x34, "*NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0x54, "*NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0x74, "*NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0xd4, "*NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0xf4, "*NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0x0c, "*NOP", 3, 4, Absolute, nop_read, Unofficial, NoPenalty),
+            (0x1c, "*NOP", 3, 4, Absolute_X, nop_read, Unofficial, PageCross),
+            (0x3c, "*NOP", 3, 4, Absolute_X, nop_read, Unofficial, PageCross),
+            (0x5c, "*NOP", 3, 4, Absolute_X, nop_read, Unofficial, PageCross),
+            (0x7c, "*NOP", 3, 4, Absolute_X, nop_read, Unofficial, PageCross),
+            (0xdc, "*NOP", 3, 4, Absolute_X, nop_read, Unofficial, PageCross),
+            (0xfc, "*NOP", 3, 4, Absolute_X, nop_read, Unofficial, PageCross),
+
+            (0x67, "*RRA", 2, 5, ZeroPage, rra, Unofficial, NoPenalty),
+            (0x77, "*RRA", 2, 6, ZeroPage_X, rra, Unofficial, NoPenalty),
+            (0x6f, "*RRA", 3, 6, Absolute, rra, Unofficial, NoPenalty),
+            (0x7f, "*RRA", 3, 7, Absolute_X, rra, Unofficial, NoPenalty),
+            (0x7b, "*RRA", 3, 7, Absolute_Y, rra, Unofficial, NoPenalty),
+            (0x63, "*RRA", 2, 8, Indirect_X, rra, Unofficial, NoPenalty),
+            (0x73, "*RRA", 2, 8, Indirect_Y, rra, Unofficial, NoPenalty),
+
+
+            (0xe7, "*ISB", 2, 5, ZeroPage, isb, Unofficial, NoPenalty),
+            (0xf7, "*ISB", 2, 6, ZeroPage_X, isb, Unofficial, NoPenalty),
+            (0xef, "*ISB", 3, 6, Absolute, isb, Unofficial, NoPenalty),
+            (0xff, "*ISB", 3, 7, Absolute_X, isb, Unofficial, NoPenalty),
+            (0xfb, "*ISB", 3, 7, Absolute_Y, isb, Unofficial, NoPenalty),
+            (0xe3, "*ISB", 2, 8, Indirect_X, isb, Unofficial, NoPenalty),
+            (0xf3, "*ISB", 2, 8, Indirect_Y, isb, Unofficial, NoPenalty),
+
+            (0x02, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0x12, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0x22, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0x32, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0x42, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0x52, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0x62, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0x72, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0x92, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0xb2, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0xd2, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+            (0xf2, "*JAM", 1, 2, NoneAddressing, jam, Unofficial, NoPenalty),
+
+            (0x1a, "*NOP", 1, 2, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x3a, "*NOP", 1, 2, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x5a, "*NOP", 1, 2, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x7a, "*NOP", 1, 2, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xda, "*NOP", 1, 2, NoneAddressing, nop, Unofficial, NoPenalty),
+            // OpCode::new(0xea, "NOP", 1,2, AddressingMode::NoneAddressing),
+            (0xfa, "*NOP", 1, 2, NoneAddressing, nop, Unofficial, NoPenalty),
+
+            // Unstable opcodes: http://www.oxyron.de/html/opcodes02.html
+            (0xab, "*LXA", 2, 2, Immediate, lxa, Unofficial, NoPenalty),
+            (0x8b, "*XAA", 2, 2, Immediate, xaa, Unofficial, NoPenalty),
+            (0xbb, "*LAS", 3, 4, Absolute_Y, las, Unofficial, PageCross),
+            (0x9b, "*TAS", 3, 5, Absolute_Y, tas, Unofficial, NoPenalty),
+            (0x93, "*AHX", 2, 6, Indirect_Y, ahx, Unofficial, NoPenalty),
+            (0x9f, "*AHX", 3, 5, Absolute_Y, ahx, Unofficial, NoPenalty),
+            (0x9e, "*SHX", 3, 5, Absolute_Y, shx, Unofficial, NoPenalty),
+            (0x9c, "*SHY", 3, 5, Absolute_X, shy, Unofficial, NoPenalty),
+
+            (0xa7, "*LAX", 2, 3, ZeroPage, lax, Unofficial, NoPenalty),
+            (0xb7, "*LAX", 2, 4, ZeroPage_Y, lax, Unofficial, NoPenalty),
+            (0xaf, "*LAX", 3, 4, Absolute, lax, Unofficial, NoPenalty),
+            (0xbf, "*LAX", 3, 4, Absolute_Y, lax, Unofficial, PageCross),
+            (0xa3, "*LAX", 2, 6, Indirect_X, lax, Unofficial, NoPenalty),
+            (0xb3, "*LAX", 2, 5, Indirect_Y, lax, Unofficial, PageCross),
+
+            (0x87, "*SAX", 2, 3, ZeroPage, sax, Unofficial, NoPenalty),
+            (0x97, "*SAX", 2, 4, ZeroPage_Y, sax, Unofficial, NoPenalty),
+            (0x8f, "*SAX", 3, 4, Absolute, sax, Unofficial, NoPenalty),
+            (0x83, "*SAX", 2, 6, Indirect_X, sax, Unofficial, NoPenalty),
+        ]}
+    };
+}
+
+pub(crate) use cpu_opcodes;
+
+/*
+    Instruction set of the WDC 65C02: the official instructions, with the JMP indirect bug fixed, and the new
+    instructions and addressing modes. The opcodes left undefined are NOPs of different lengths.
+*/
+macro_rules! cpu_opcodes_65c02 {
+    ($table:ident) => {
+        $crate::opcodes::official_opcodes! { $table, [
+            (0x6C, "JMP", 3, 6, NoneAddressing, jmp_indirect_fixed, Official, NoPenalty),
+            (0x7C, "JMP", 3, 6, NoneAddressing, jmp_indirect_x, Official, NoPenalty),
+
+            (0x80, "BRA", 2, 2, NoneAddressing, bra, Official, NoPenalty),
+
+            /* Shifts and rotations with abs,X only take the extra cycle when the page is crossed, unlike INC and DEC */
+            (0x1E, "ASL", 3, 6, Absolute_X, asl, Official, PageCross),
+            (0x5E, "LSR", 3, 6, Absolute_X, lsr, Official, PageCross),
+            (0x3E, "ROL", 3, 6, Absolute_X, rol, Official, PageCross),
+            (0x7E, "ROR", 3, 6, Absolute_X, ror, Official, PageCross),
+
+            /* Zero page indirect addressing */
+            (0x72, "ADC", 2, 5, ZeroPage_Indirect, adc, Official, NoPenalty),
+            (0xF2, "SBC", 2, 5, ZeroPage_Indirect, sbc, Official, NoPenalty),
+            (0x32, "AND", 2, 5, ZeroPage_Indirect, and, Official, NoPenalty),
+            (0x52, "EOR", 2, 5, ZeroPage_Indirect, eor, Official, NoPenalty),
+            (0x12, "ORA", 2, 5, ZeroPage_Indirect, ora, Official, NoPenalty),
+            (0xD2, "CMP", 2, 5, ZeroPage_Indirect, cmp, Official, NoPenalty),
+            (0xB2, "LDA", 2, 5, ZeroPage_Indirect, lda, Official, NoPenalty),
+            (0x92, "STA", 2, 5, ZeroPage_Indirect, sta, Official, NoPenalty),
+
+            (0x89, "BIT", 2, 2, Immediate, bit_immediate, Official, NoPenalty),
+            (0x34, "BIT", 2, 4, ZeroPage_X, bit, Official, NoPenalty),
+            (0x3C, "BIT", 3, 4, Absolute_X, bit, Official, PageCross),
+
+            (0x1A, "INC", 1, 2, NoneAddressing, inc_accumulator, Official, NoPenalty),
+            (0x3A, "DEC", 1, 2, NoneAddressing, dec_accumulator, Official, NoPenalty),
+
+            (0xDA, "PHX", 1, 3, NoneAddressing, phx, Official, NoPenalty),
+            (0xFA, "PLX", 1, 4, NoneAddressing, plx, Official, NoPenalty),
+            (0x5A, "PHY", 1, 3, NoneAddressing, phy, Official, NoPenalty),
+            (0x7A, "PLY", 1, 4, NoneAddressing, ply, Official, NoPenalty),
+
+            (0x64, "STZ", 2, 3, ZeroPage, stz, Official, NoPenalty),
+            (0x74, "STZ", 2, 4, ZeroPage_X, stz, Official, NoPenalty),
+            (0x9C, "STZ", 3, 4, Absolute, stz, Official, NoPenalty),
+            (0x9E, "STZ", 3, 5, Absolute_X, stz, Official, NoPenalty),
+
+            (0x04, "TSB", 2, 5, ZeroPage, tsb, Official, NoPenalty),
+            (0x0C, "TSB", 3, 6, Absolute, tsb, Official, NoPenalty),
+            (0x14, "TRB", 2, 5, ZeroPage, trb, Official, NoPenalty),
+            (0x1C, "TRB", 3, 6, Absolute, trb, Official, NoPenalty),
+
+            /* Bit manipulation. The number of the bit is encoded in the opcode. */
+            (0x07, "RMB0", 2, 5, ZeroPage, rmb, Official, NoPenalty),
+            (0x17, "RMB1", 2, 5, ZeroPage, rmb, Official, NoPenalty),
+            (0x27, "RMB2", 2, 5, ZeroPage, rmb, Official, NoPenalty),
+            (0x37, "RMB3", 2, 5, ZeroPage, rmb, Official, NoPenalty),
+            (0x47, "RMB4", 2, 5, ZeroPage, rmb, Official, NoPenalty),
+            (0x57, "RMB5", 2, 5, ZeroPage, rmb, Official, NoPenalty),
+            (0x67, "RMB6", 2, 5, ZeroPage, rmb, Official, NoPenalty),
+            (0x77, "RMB7", 2, 5, ZeroPage, rmb, Official, NoPenalty),
+
+            (0x87, "SMB0", 2, 5, ZeroPage, smb, Official, NoPenalty),
+            (0x97, "SMB1", 2, 5, ZeroPage, smb, Official, NoPenalty),
+            (0xA7, "SMB2", 2, 5, ZeroPage, smb, Official, NoPenalty),
+            (0xB7, "SMB3", 2, 5, ZeroPage, smb, Official, NoPenalty),
+            (0xC7, "SMB4", 2, 5, ZeroPage, smb, Official, NoPenalty),
+            (0xD7, "SMB5", 2, 5, ZeroPage, smb, Official, NoPenalty),
+            (0xE7, "SMB6", 2, 5, ZeroPage, smb, Official, NoPenalty),
+            (0xF7, "SMB7", 2, 5, ZeroPage, smb, Official, NoPenalty),
+
+            (0x0F, "BBR0", 3, 5, ZeroPage_Relative, bbr, Official, NoPenalty),
+            (0x1F, "BBR1", 3, 5, ZeroPage_Relative, bbr, Official, NoPenalty),
+            (0x2F, "BBR2", 3, 5, ZeroPage_Relative, bbr, Official, NoPenalty),
+            (0x3F, "BBR3", 3, 5, ZeroPage_Relative, bbr, Official, NoPenalty),
+            (0x4F, "BBR4", 3, 5, ZeroPage_Relative, bbr, Official, NoPenalty),
+            (0x5F, "BBR5", 3, 5, ZeroPage_Relative, bbr, Official, NoPenalty),
+            (0x6F, "BBR6", 3, 5, ZeroPage_Relative, bbr, Official, NoPenalty),
+            (0x7F, "BBR7", 3, 5, ZeroPage_Relative, bbr, Official, NoPenalty),
+
+            (0x8F, "BBS0", 3, 5, ZeroPage_Relative, bbs, Official, NoPenalty),
+            (0x9F, "BBS1", 3, 5, ZeroPage_Relative, bbs, Official, NoPenalty),
+            (0xAF, "BBS2", 3, 5, ZeroPage_Relative, bbs, Official, NoPenalty),
+            (0xBF, "BBS3", 3, 5, ZeroPage_Relative, bbs, Official, NoPenalty),
+            (0xCF, "BBS4", 3, 5, ZeroPage_Relative, bbs, Official, NoPenalty),
+            (0xDF, "BBS5", 3, 5, ZeroPage_Relative, bbs, Official, NoPenalty),
+            (0xEF, "BBS6", 3, 5, ZeroPage_Relative, bbs, Official, NoPenalty),
+            (0xFF, "BBS7", 3, 5, ZeroPage_Relative, bbs, Official, NoPenalty),
+
+            (0xCB, "WAI", 1, 3, NoneAddressing, wai, Official, NoPenalty),
+            (0xDB, "STP", 1, 3, NoneAddressing, stp, Official, NoPenalty),
+
+            /* Undefined opcodes, which do nothing */
+            (0x02, "NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0x22, "NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0x42, "NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0x62, "NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0x82, "NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0xC2, "NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0xE2, "NOP", 2, 2, Immediate, nop_read, Unofficial, NoPenalty),
+            (0x44, "NOP", 2, 3, ZeroPage, nop_read, Unofficial, NoPenalty),
+            (0x54, "NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0xD4, "NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0xF4, "NOP", 2, 4, ZeroPage_X, nop_read, Unofficial, NoPenalty),
+            (0x5C, "NOP", 3, 8, Absolute, nop_long, Unofficial, NoPenalty),
+            (0xDC, "NOP", 3, 4, Absolute, nop_read, Unofficial, NoPenalty),
+            (0xFC, "NOP", 3, 4, Absolute, nop_read, Unofficial, NoPenalty),
+
+            (0x03, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x13, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x23, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x33, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x43, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x53, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x63, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x73, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x83, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x93, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xA3, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xB3, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xC3, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xD3, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xE3, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xF3, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x0B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x1B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x2B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x3B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x4B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x5B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x6B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x7B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x8B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0x9B, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xAB, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xBB, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xEB, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+            (0xFB, "NOP", 1, 1, NoneAddressing, nop, Unofficial, NoPenalty),
+        ]}
+    };
+}
+
+pub(crate) use cpu_opcodes_65c02;
+
+macro_rules! opcodes_list {
+    ($((
+        $code:expr, $mnemonic:expr, $len:expr, $cycles:expr, $mode:ident, $handler:ident, $official:ident,
+        $penalty:ident
+    )),* $(,)?) => {
+        &[
+            $({
+                let (category, flags) = instruction_metadata!($handler);
+                OpCode {
+                    code: $code,
+                    mnemonic: $mnemonic,
+                    len: $len,
+                    cycles: $cycles,
+                    mode: AddressingMode::$mode,
+                    category,
+                    official: official!($official),
+                    flags: parse_flags(flags),
+                    page_cross_penalty: page_cross_penalty!($penalty)
+                }
+            }),*
+        ]
+    };
+}
+
+pub static CPU_OPS_CODES: &[OpCode] = cpu_opcodes!(opcodes_list);
+pub static CPU_OPS_CODES_65C02: &[OpCode] = cpu_opcodes_65c02!(opcodes_list);
+
+/*
+    Tables with the metadata of every opcode, indexed by the opcode itself.
+*/
+pub static OPCODES_TABLE: [OpCode; 256] = opcodes_table(CPU_OPS_CODES);
+pub static OPCODES_TABLE_65C02: [OpCode; 256] = opcodes_table(CPU_OPS_CODES_65C02);
+
+/*
+    Instruction set of the given CPU model.
+*/
+pub fn instruction_set(model: CpuModel) -> &'static [OpCode] {
+    match model {
+        CpuModel::Ricoh2A03 | CpuModel::Nmos6502 => CPU_OPS_CODES,
+        CpuModel::Wdc65C02 => CPU_OPS_CODES_65C02
+    }
+}
+
+/*
+    Table with the metadata of the opcodes of the given CPU model, indexed by the opcode itself.
+*/
+pub fn table(model: CpuModel) -> &'static [OpCode; 256] {
+    match model {
+        CpuModel::Ricoh2A03 | CpuModel::Nmos6502 => &OPCODES_TABLE,
+        CpuModel::Wdc65C02 => &OPCODES_TABLE_65C02
+    }
+}
+
+const fn opcodes_table(opcodes: &[OpCode]) -> [OpCode; 256] {
+    let undefined = OpCode {
+        code: 0x00,
+        mnemonic: "",
+        len: 0,
+        cycles: 0,
+        mode: AddressingMode::NoneAddressing,
+        category: Category::Implied,
+        official: false,
+        flags: CpuFlags::empty(),
+        page_cross_penalty: false
+    };
+    let mut table = [undefined; 256];
+    let mut defined = [false; 256];
+
+    let mut i = 0;
+    while i < opcodes.len() {
+        let code = opcodes[i].code as usize;
+        if defined[code] {
+            panic!("Opcode defined more than once");
+        }
+        table[code] = opcodes[i];
+        defined[code] = true;
+        i += 1;
+    }
+
+    // Every byte has to be decoded into some instruction
+    let mut code = 0;
+    while code < 256 {
+        if !defined[code] {
+            panic!("Opcode not defined");
+        }
+        code += 1;
+    }
+
+    table
+}
@@ -0,0 +1,77 @@
+use crate::cpu::Mem;
+
+const RAM_SIZE: usize = 0x10000;
+
+/*
+    Flat memory that maps the whole 64 KiB address space to RAM, without mirrors, I/O registers or cartridge.
+    It lets the CPU run generic 6502 programs and test suites outside of the NES memory map.
+*/
+pub struct Ram {
+    memory: Vec<u8>
+}
+
+impl Ram {
+    pub fn new() -> Self {
+        Ram {
+            memory: vec![0; RAM_SIZE]
+        }
+    }
+
+    /*
+        Copy the program to memory, starting at the given address.
+    */
+    pub fn load(&mut self, address: u16, program: &[u8]) {
+        let start = address as usize;
+        let end = start + program.len();
+        assert!(end <= RAM_SIZE, "Program does not fit in memory");
+        self.memory[start..end].copy_from_slice(program);
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Ram::new()
+    }
+}
+
+impl Mem for Ram {
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn mem_write(&mut self, address: u16, data: u8) {
+        self.memory[address as usize] = data;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn test_cpu_runs_on_flat_ram() {
+        // LDA #$C0; STA $1234; TAX; INX; BRK
+        let mut ram = Ram::new();
+        ram.load(0x0600, &[0xa9, 0xc0, 0x8d, 0x34, 0x12, 0xaa, 0xe8, 0x00]);
+        ram.mem_write_u16(0xFFFC, 0x0600);
+        let mut cpu = CPU::new(ram);
+        cpu.halt_opcode = Some(0x00);
+
+        cpu.reset();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.register_x, 0xc1);
+        assert_eq!(cpu.program_counter, 0x0607);
+        assert_eq!(cpu.bus.mem_read(0x1234), 0xc0);
+    }
+
+    #[test]
+    fn test_read_u16_wraps_around_address_space() {
+        let mut ram = Ram::new();
+        ram.mem_write(0xFFFF, 0x34);
+        ram.mem_write(0x0000, 0x12);
+
+        assert_eq!(ram.mem_read_u16(0xFFFF), 0x1234);
+    }
+}
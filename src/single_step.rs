@@ -0,0 +1,494 @@
+use crate::cpu::CpuError;
+use crate::cpu::CpuFlags;
+use crate::cpu::CpuModel;
+use crate::cpu::Mem;
+use crate::cpu::CPU;
+use std::fmt;
+
+/*
+    Harness for the per-opcode single-step test vectors (https://github.com/SingleStepTests/65x02).
+
+    Each file has thousands of cases for one opcode. A case gives the registers and the RAM contents before and
+    after executing a single instruction, and the list of bus accesses done in each cycle:
+
+    {
+        "name": "a9 10 20",
+        "initial": { "pc": 1234, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[1234, 169], [1235, 16]] },
+        "final": { "pc": 1236, "s": 253, "a": 16, "x": 0, "y": 0, "p": 36, "ram": [[1234, 169], [1235, 16]] },
+        "cycles": [[1234, 169, "read"], [1235, 16, "read"]]
+    }
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub ram: Vec<(u16, u8)>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusCycle {
+    pub address: u16,
+    pub value: u8,
+    pub write: bool
+}
+
+impl fmt::Display for BusCycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = if self.write { "write" } else { "read" };
+        write!(f, "{} {:04X} = {:02X}", access, self.address, self.value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: CpuState,
+    pub expected: CpuState,
+    pub cycles: Vec<BusCycle>
+}
+
+/*
+    A difference between the expected and the actual state after running a case.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Register { name: &'static str, expected: u16, actual: u16 },
+    Ram { address: u16, expected: u8, actual: u8 },
+    Cycles { expected: Vec<BusCycle>, actual: Vec<BusCycle> },
+    Error(CpuError)
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Register { name, expected, actual } => {
+                write!(f, "{}: expected {:02X}, got {:02X}", name, expected, actual)
+            }
+            Mismatch::Ram { address, expected, actual } => {
+                write!(f, "RAM[{:04X}]: expected {:02X}, got {:02X}", address, expected, actual)
+            }
+            Mismatch::Cycles { expected, actual } => {
+                write!(f, "cycles: expected {} bus accesses, got {}", expected.len(), actual.len())?;
+                // Point to the first access that differs
+                let index = expected.iter().zip(actual.iter()).take_while(|(e, a)| e == a).count();
+                match (expected.get(index), actual.get(index)) {
+                    (Some(e), Some(a)) => write!(f, "; cycle {}: expected {}, got {}", index + 1, e, a),
+                    (Some(e), None) => write!(f, "; cycle {}: expected {}", index + 1, e),
+                    (None, Some(a)) => write!(f, "; cycle {}: unexpected {}", index + 1, a),
+                    (None, None) => Ok(())
+                }
+            }
+            Mismatch::Error(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+/*
+    Flat 64 KiB memory that records every access of the CPU, to compare it with the cycle list of the vectors.
+*/
+pub struct LoggedRam {
+    memory: Vec<u8>,
+    log: Vec<BusCycle>
+}
+
+impl LoggedRam {
+    pub fn new() -> Self {
+        LoggedRam {
+            memory: vec![0; 0x10000],
+            log: vec![]
+        }
+    }
+
+    pub fn take_log(&mut self) -> Vec<BusCycle> {
+        std::mem::take(&mut self.log)
+    }
+}
+
+impl Default for LoggedRam {
+    fn default() -> Self {
+        LoggedRam::new()
+    }
+}
+
+impl Mem for LoggedRam {
+    fn mem_read(&mut self, address: u16) -> u8 {
+        let value = self.memory[address as usize];
+        self.log.push(BusCycle { address, value, write: false });
+        value
+    }
+
+    fn mem_write(&mut self, address: u16, data: u8) {
+        self.memory[address as usize] = data;
+        self.log.push(BusCycle { address, value: data, write: true });
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+}
+
+/*
+    Load the initial state, execute one instruction in a CPU of the given model and compare every field with the
+    final state. Returns the list of mismatches, which is empty if the case passed.
+*/
+pub fn run_case(case: &TestCase, model: CpuModel) -> Vec<Mismatch> {
+    let mut ram = LoggedRam::new();
+    for &(address, value) in &case.initial.ram {
+        ram.memory[address as usize] = value;
+    }
+
+    let mut cpu = CPU::with_model(ram, model);
+    cpu.program_counter = case.initial.pc;
+    cpu.stack_pointer = case.initial.s;
+    cpu.register_a = case.initial.a;
+    cpu.register_x = case.initial.x;
+    cpu.register_y = case.initial.y;
+    cpu.status = CpuFlags::from_bits_truncate(case.initial.p);
+
+    // The JAM opcodes stop the CPU with an error, but the vectors still give the state in which they leave it
+    let mut mismatches = vec![];
+    match cpu.step() {
+        Ok(_) | Err(CpuError::Jammed { .. }) => {}
+        Err(error) => mismatches.push(Mismatch::Error(error))
+    }
+
+    let expected = &case.expected;
+    let registers = [
+        ("PC", expected.pc, cpu.program_counter),
+        ("SP", expected.s as u16, cpu.stack_pointer as u16),
+        ("A", expected.a as u16, cpu.register_a as u16),
+        ("X", expected.x as u16, cpu.register_x as u16),
+        ("Y", expected.y as u16, cpu.register_y as u16),
+        ("P", expected.p as u16, cpu.status.bits() as u16),
+    ];
+    for &(name, expected, actual) in registers.iter() {
+        if expected != actual {
+            mismatches.push(Mismatch::Register { name, expected, actual });
+        }
+    }
+
+    for &(address, value) in &expected.ram {
+        let actual = cpu.bus.memory[address as usize];
+        if actual != value {
+            mismatches.push(Mismatch::Ram { address, expected: value, actual });
+        }
+    }
+
+    let actual_cycles = cpu.bus.take_log();
+    if actual_cycles != case.cycles {
+        mismatches.push(Mismatch::Cycles { expected: case.cycles.clone(), actual: actual_cycles });
+    }
+
+    mismatches
+}
+
+/*
+    Parse the contents of a file of test vectors.
+*/
+pub fn parse_tests(text: &str) -> Result<Vec<TestCase>, String> {
+    let mut parser = JsonParser { text: text.as_bytes(), position: 0 };
+    let json = parser.parse_value()?;
+    json.as_array()?.iter().map(parse_case).collect()
+}
+
+fn parse_case(json: &Json) -> Result<TestCase, String> {
+    let cycles = json.get("cycles")?.as_array()?.iter().map(|cycle| {
+        let fields = cycle.as_array()?;
+        if fields.len() != 3 {
+            return Err(String::from("Expected [address, value, access] in the cycle list"));
+        }
+        Ok(BusCycle {
+            address: fields[0].as_number()? as u16,
+            value: fields[1].as_number()? as u8,
+            write: fields[2].as_string()? == "write"
+        })
+    }).collect::<Result<Vec<BusCycle>, String>>()?;
+
+    Ok(TestCase {
+        name: json.get("name")?.as_string()?.to_string(),
+        initial: parse_state(json.get("initial")?)?,
+        expected: parse_state(json.get("final")?)?,
+        cycles
+    })
+}
+
+fn parse_state(json: &Json) -> Result<CpuState, String> {
+    let ram = json.get("ram")?.as_array()?.iter().map(|entry| {
+        let fields = entry.as_array()?;
+        if fields.len() != 2 {
+            return Err(String::from("Expected [address, value] in the RAM list"));
+        }
+        Ok((fields[0].as_number()? as u16, fields[1].as_number()? as u8))
+    }).collect::<Result<Vec<(u16, u8)>, String>>()?;
+
+    Ok(CpuState {
+        pc: json.get("pc")?.as_number()? as u16,
+        s: json.get("s")?.as_number()? as u8,
+        a: json.get("a")?.as_number()? as u8,
+        x: json.get("x")?.as_number()? as u8,
+        y: json.get("y")?.as_number()? as u8,
+        p: json.get("p")?.as_number()? as u8,
+        ram
+    })
+}
+
+/*
+    Minimal JSON reader, enough for the test vectors. Numbers are kept as integers, since the vectors only have
+    addresses and byte values.
+*/
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Result<&Json, String> {
+        match self {
+            Json::Object(fields) => fields.iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value)
+                .ok_or(format!("Missing field \"{}\"", key)),
+            _ => Err(format!("Expected an object with the field \"{}\"", key))
+        }
+    }
+
+    fn as_array(&self) -> Result<&Vec<Json>, String> {
+        match self {
+            Json::Array(values) => Ok(values),
+            _ => Err(String::from("Expected an array"))
+        }
+    }
+
+    fn as_number(&self) -> Result<i64, String> {
+        match self {
+            Json::Number(value) => Ok(*value),
+            _ => Err(String::from("Expected a number"))
+        }
+    }
+
+    fn as_string(&self) -> Result<&str, String> {
+        match self {
+            Json::String(value) => Ok(value),
+            _ => Err(String::from("Expected a string"))
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    text: &'a [u8],
+    position: usize
+}
+
+impl<'a> JsonParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.text.len() && self.text[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at offset {}", byte as char, self.position))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.position..].starts_with(keyword.as_bytes()) {
+            self.position += keyword.len();
+            Ok(value)
+        } else {
+            Err(format!("Unexpected token at offset {}", self.position))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => Ok(Json::String(self.parse_string()?)),
+            Some(b't') => self.expect_keyword("true", Json::Bool(true)),
+            Some(b'f') => self.expect_keyword("false", Json::Bool(false)),
+            Some(b'n') => self.expect_keyword("null", Json::Null),
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(format!("Unexpected character at offset {}", self.position)),
+            None => Err(String::from("Unexpected end of input"))
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut fields = vec![];
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            let key = self.parse_string()?;
+            self.expect(b':')?;
+            fields.push((key, self.parse_value()?));
+            if self.peek() == Some(b',') {
+                self.position += 1;
+            } else {
+                self.expect(b'}')?;
+                return Ok(Json::Object(fields));
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut values = vec![];
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            if self.peek() == Some(b',') {
+                self.position += 1;
+            } else {
+                self.expect(b']')?;
+                return Ok(Json::Array(values));
+            }
+        }
+    }
+
+    /*
+        Strings in the vectors are plain ASCII (names and access types), so escapes are only skipped over.
+    */
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let start = self.position;
+        while self.position < self.text.len() && self.text[self.position] != b'"' {
+            if self.text[self.position] == b'\\' {
+                self.position += 1;
+            }
+            self.position += 1;
+        }
+        if self.position >= self.text.len() {
+            return Err(String::from("Unterminated string"));
+        }
+        let value = String::from_utf8_lossy(&self.text[start..self.position]).to_string();
+        self.position += 1;
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        if self.text[self.position] == b'-' {
+            self.position += 1;
+        }
+        while self.position < self.text.len() && self.text[self.position].is_ascii_digit() {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.position]).unwrap()
+            .parse::<i64>()
+            .map(Json::Number)
+            .map_err(|_| format!("Invalid number at offset {}", start))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::opcodes;
+
+    const LDA_IMMEDIATE: &str = r#"[
+        {
+            "name": "a9 80 20",
+            "initial": { "pc": 1024, "s": 253, "a": 1, "x": 2, "y": 3, "p": 38, "ram": [[1024, 169], [1025, 128]] },
+            "final": { "pc": 1026, "s": 253, "a": 128, "x": 2, "y": 3, "p": 164, "ram": [[1024, 169], [1025, 128]] },
+            "cycles": [[1024, 169, "read"], [1025, 128, "read"]]
+        }
+    ]"#;
+
+    #[test]
+    fn test_parse_and_run_passing_case() {
+        let cases = parse_tests(LDA_IMMEDIATE).unwrap();
+        assert_eq!(cases.len(), 1);
+        assert_eq!(cases[0].name, "a9 80 20");
+        assert_eq!(cases[0].initial.ram, vec![(0x0400, 0xa9), (0x0401, 0x80)]);
+        assert_eq!(cases[0].cycles[1], BusCycle { address: 0x0401, value: 0x80, write: false });
+
+        assert_eq!(run_case(&cases[0], CpuModel::Ricoh2A03), vec![]);
+    }
+
+    #[test]
+    fn test_reports_mismatches_per_field() {
+        let mut case = parse_tests(LDA_IMMEDIATE).unwrap().remove(0);
+        case.expected.a = 0x81;
+        case.expected.ram[1].1 = 0x00;
+        case.cycles.push(BusCycle { address: 0x0402, value: 0x00, write: false });
+
+        let mismatches = run_case(&case, CpuModel::Ricoh2A03);
+
+        assert_eq!(mismatches.len(), 3);
+        assert_eq!(mismatches[0], Mismatch::Register { name: "A", expected: 0x81, actual: 0x80 });
+        assert_eq!(mismatches[1], Mismatch::Ram { address: 0x0401, expected: 0x00, actual: 0x80 });
+        assert_eq!(mismatches[2].to_string(), "cycles: expected 3 bus accesses, got 2; cycle 3: expected read 0402 = 00");
+    }
+
+    #[test]
+    fn test_jam_is_compared_with_final_state() {
+        // Turn the case into a JAM, which leaves the program counter at the opcode
+        let mut case = parse_tests(LDA_IMMEDIATE).unwrap().remove(0);
+        case.initial.ram[0].1 = 0x02;
+        case.expected.ram[0].1 = 0x02;
+
+        let mismatches = run_case(&case, CpuModel::Ricoh2A03);
+
+        assert!(!mismatches.iter().any(|mismatch| matches!(mismatch, Mismatch::Error(_))));
+        assert_eq!(mismatches[0], Mismatch::Register { name: "PC", expected: 0x0402, actual: 0x0400 });
+    }
+
+    /*
+        Run the vectors of all the 256 opcodes, which have to be placed in test_roms/nes6502 (one file per opcode,
+        named after it in lowercase hexadecimal, like a9.json). The summary printed at the end is the result to
+        record:
+        cargo test --release -- --ignored single_step --nocapture
+    */
+    #[test]
+    #[ignore]
+    fn test_single_step_vectors() {
+        let (mut failed_opcodes, mut total_cases) = (0, 0);
+        for opcode in opcodes::table(CpuModel::Ricoh2A03).iter() {
+            let path = format!("test_roms/nes6502/{:02x}.json", opcode.code);
+            let text = std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Missing test vectors {}", path));
+            let cases = parse_tests(&text).unwrap();
+            total_cases += cases.len();
+
+            let failures: Vec<(&TestCase, Vec<Mismatch>)> = cases.iter()
+                .map(|case| (case, run_case(case, CpuModel::Ricoh2A03)))
+                .filter(|(_, mismatches)| !mismatches.is_empty())
+                .collect();
+            if let Some((case, mismatches)) = failures.first() {
+                failed_opcodes += 1;
+                println!("{:02X} {}: {} of {} cases failed, first \"{}\":",
+                    opcode.code, opcode.mnemonic, failures.len(), cases.len(), case.name);
+                for mismatch in mismatches {
+                    println!("    {}", mismatch);
+                }
+            }
+        }
+        println!("256 opcodes, {} cases: {} opcodes failed", total_cases, failed_opcodes);
+        assert_eq!(failed_opcodes, 0);
+    }
+}
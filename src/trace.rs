@@ -1,34 +1,224 @@
 use crate::cpu::AddressingMode;
 use crate::cpu::Mem;
+use crate::cpu::CpuModel;
 use crate::cpu::CPU;
 use crate::opcodes;
-use std::collections::HashMap;
 
-pub fn trace(cpu: &CPU) -> String {
-    let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
+/*
+    Formats of the trace, one line per instruction. Each one shows the instruction at the program counter, before it
+    is executed, and the state of the CPU. Memory is only peeked at, so tracing does not change the behavior of the
+    devices on the bus.
+    The position of the PPU is the one given by the bus. When the bus has no PPU, as long as the NES one does not
+    emulate it, the position is only an estimate computed from the CPU cycles (see estimated_ppu_position).
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceFormat {
+    Nintendulator,      // the log of nestest: registers, position of the PPU (scanline and dot) and CPU cycles
+    NintendulatorShort, // the same, ending with the registers
+    Mesen,              // in the style of the trace logger of Mesen
+    Fceux,              // in the style of the trace logger of FCEUX, with the registers first
+    Template(Template)  // defined by the user
+}
+
+impl TraceFormat {
+    pub fn format<M: Mem>(&self, cpu: &CPU<M>) -> String {
+        let instruction = decode(cpu);
+        let (scanline, dot) = cpu.bus.ppu_position().unwrap_or_else(|| estimated_ppu_position(cpu.cycles));
+        let status = cpu.status.bits();
+
+        match self {
+            TraceFormat::Nintendulator => format!(
+                "{} PPU:{:3},{:3} CYC:{}",
+                TraceFormat::NintendulatorShort.format(cpu), scanline, dot, cpu.cycles
+            ),
+            TraceFormat::NintendulatorShort => {
+                // Unofficial mnemonics take the place of the space before the mnemonic
+                let asm = format!(
+                    "{:04X}  {:8} {: >4} {}",
+                    instruction.pc, instruction.hex_bytes(), instruction.mnemonic, instruction.operand
+                );
+                format!(
+                    "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                    asm.trim_end(), cpu.register_a, cpu.register_x, cpu.register_y, status, cpu.stack_pointer
+                )
+            }
+            TraceFormat::Mesen => format!(
+                "{:04X}  {:30} A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{} V:{:<3} H:{:<3} Cycle:{}",
+                instruction.pc, instruction.disassembly(), cpu.register_a, cpu.register_x, cpu.register_y,
+                cpu.stack_pointer, flag_letters(status, "NV-BDIZC"), scanline, dot, cpu.cycles
+            ),
+            TraceFormat::Fceux => format!(
+                "A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  ${:04X}:{:8}  {}",
+                cpu.register_a, cpu.register_x, cpu.register_y, cpu.stack_pointer, flag_letters(status, "NVUBDIZC"),
+                instruction.pc, instruction.hex_bytes(), instruction.disassembly()
+            ),
+            TraceFormat::Template(template) => template.parts.iter().map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Value(Placeholder::Pc) => format!("{:04X}", instruction.pc),
+                Part::Value(Placeholder::Bytes) => instruction.hex_bytes(),
+                Part::Value(Placeholder::Disassembly) => instruction.disassembly(),
+                Part::Value(Placeholder::A) => format!("{:02X}", cpu.register_a),
+                Part::Value(Placeholder::X) => format!("{:02X}", cpu.register_x),
+                Part::Value(Placeholder::Y) => format!("{:02X}", cpu.register_y),
+                Part::Value(Placeholder::P) => format!("{:02X}", status),
+                Part::Value(Placeholder::Sp) => format!("{:02X}", cpu.stack_pointer),
+                Part::Value(Placeholder::Flags) => flag_letters(status, "NV-BDIZC"),
+                Part::Value(Placeholder::Cycles) => cpu.cycles.to_string(),
+                Part::Value(Placeholder::Scanline) => scanline.to_string(),
+                Part::Value(Placeholder::Dot) => dot.to_string()
+            }).collect()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Pc,
+    Bytes,
+    Disassembly,
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Flags,
+    Cycles,
+    Scanline,
+    Dot
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Value(Placeholder)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>
+}
+
+impl Template {
+    /*
+        Parse a template, where the values are given by placeholders between braces:
+            {PC} {BYTES} {A} {X} {Y} {P} {SP}   in hexadecimal
+            {DISASM}                            the instruction, with the addresses and values of its operand
+            {FLAGS}                             the status as the letters of "NV-BDIZC", in lowercase when clear
+            {CYC} {SCANLINE} {DOT}              the CPU cycles and the position of the PPU, in decimal
+    */
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+            let close = rest[open..].find('}').ok_or(format!("Unclosed placeholder in {}", template))? + open;
+            let placeholder = match rest[open + 1..close].to_uppercase().as_str() {
+                "PC" => Placeholder::Pc,
+                "BYTES" => Placeholder::Bytes,
+                "DISASM" => Placeholder::Disassembly,
+                "A" => Placeholder::A,
+                "X" => Placeholder::X,
+                "Y" => Placeholder::Y,
+                "P" => Placeholder::P,
+                "SP" => Placeholder::Sp,
+                "FLAGS" => Placeholder::Flags,
+                "CYC" => Placeholder::Cycles,
+                "SCANLINE" => Placeholder::Scanline,
+                "DOT" => Placeholder::Dot,
+                _ => return Err(format!("Unknown placeholder {}", &rest[open..=close]))
+            };
+            parts.push(Part::Value(placeholder));
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+}
+
+/*
+    Format the instruction at the program counter and the state of the CPU, like the log of nestest.
+*/
+pub fn trace<M: Mem>(cpu: &CPU<M>) -> String {
+    TraceFormat::Nintendulator.format(cpu)
+}
+
+/*
+    The short format of the log of nestest, which ends with the registers.
+*/
+pub fn trace_short<M: Mem>(cpu: &CPU<M>) -> String {
+    TraceFormat::NintendulatorShort.format(cpu)
+}
+
+/*
+    Approximate position of the PPU after the given number of CPU cycles since power up, for the memory without a
+    PPU. It assumes 3 dots per CPU cycle, with 341 dots per scanline and 262 scanlines per frame (NTSC), and does not
+    skip the dot of the odd frames, so it drifts from a real PPU that renders. It matches the log of nestest, which
+    does not enable rendering.
+*/
+fn estimated_ppu_position(cycles: u64) -> (u16, u16) {
+    let dots = cycles * 3;
+    (((dots / 341) % 262) as u16, (dots % 341) as u16)
+}
+
+/*
+    The status as letters, one per bit from the highest, in uppercase when set and in lowercase when clear.
+*/
+fn flag_letters(status: u8, letters: &str) -> String {
+    letters.chars().enumerate()
+        .map(|(bit, letter)| if status & (0x80 >> bit) != 0 {
+            letter.to_ascii_uppercase()
+        } else {
+            letter.to_ascii_lowercase()
+        })
+        .collect()
+}
 
-    let code = cpu.mem_read(cpu.program_counter);
-    let ops = opcodes.get(&code).unwrap();
+/*
+    The instruction at the program counter, with the operand annotated with the addresses it resolves to and the
+    values in memory, as in the log of nestest.
+*/
+struct TracedInstruction {
+    pc: u16,
+    bytes: Vec<u8>,
+    mnemonic: &'static str,
+    operand: String
+}
+
+impl TracedInstruction {
+    fn hex_bytes(&self) -> String {
+        self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(" ")
+    }
+
+    fn disassembly(&self) -> String {
+        format!("{} {}", self.mnemonic, self.operand).trim_end().to_string()
+    }
+}
+
+fn decode<M: Mem>(cpu: &CPU<M>) -> TracedInstruction {
+    let code = cpu.peek(cpu.program_counter);
+    let ops = &opcodes::table(cpu.model())[code as usize];
 
     let begin = cpu.program_counter;
     let mut hex_dump = vec![];
     hex_dump.push(code);
 
-    let (mem_addr, stored_value) = match ops.mode {
-        AddressingMode::Immediate | AddressingMode::NoneAddressing => (0, 0),
-        _ => {
-            let addr = cpu.get_absolute_address(&ops.mode, begin + 1);
-            (addr, cpu.mem_read(addr))
-        }
+    let (mem_addr, stored_value) = match cpu.get_absolute_address(&ops.mode, begin.wrapping_add(1)) {
+        Ok((addr, _)) if ops.mode != AddressingMode::Immediate => (addr, cpu.peek(addr)),
+        _ => (0, 0)
     };
 
     let tmp = match ops.len {
         1 => match ops.code {
-            0x0a | 0x4a | 0x2a | 0x6a => format!("A "),
+            0x0a | 0x4a | 0x2a | 0x6a => String::from("A "),
+            0x1a | 0x3a if cpu.model() == CpuModel::Wdc65C02 => String::from("A "),
             _ => String::from("")
         },
         2 => {
-            let address: u8 = cpu.mem_read(begin + 1);
+            let address: u8 = cpu.peek(begin.wrapping_add(1));
             hex_dump.push(address);
 
             match ops.mode {
@@ -56,33 +246,41 @@ pub fn trace(cpu: &CPU) -> String {
                     mem_addr,
                     stored_value
                 ),
+                AddressingMode::ZeroPage_Indirect => format!(
+                    "(${:02x}) = {:04x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
                 AddressingMode::NoneAddressing => {
-                    let address: usize = (begin as usize + 2).wrapping_add((address as i8) as usize);
+                    let address = begin.wrapping_add(2).wrapping_add((address as i8) as u16);
                     format!("${:04x}", address)
                 },
                 _ => panic!("Unexpected addressing mode {:?} has ops-len 2. code {:02x}", ops.mode, ops.code),
             }
         },
         3 => {
-            let address_lo = cpu.mem_read(begin + 1);
-            let address_hi = cpu.mem_read(begin + 2);
+            let address_lo = cpu.peek(begin.wrapping_add(1));
+            let address_hi = cpu.peek(begin.wrapping_add(2));
             hex_dump.push(address_lo);
             hex_dump.push(address_hi);
 
-            let address = cpu.mem_read_u16(begin + 1);
+            let address = cpu.peek_u16(begin.wrapping_add(1));
 
             match ops.mode {
                 AddressingMode::NoneAddressing => {
                     if ops.code == 0x6c {
-                        // Code corresponding to JMP indirect
-                        let jmp_address = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.mem_read(address);
-                            let hi = cpu.mem_read(address & 0xFF00);
+                        // Code corresponding to JMP indirect. The page wrap bug is fixed in the 65C02.
+                        let jmp_address = if address & 0x00FF == 0x00FF && cpu.model() != CpuModel::Wdc65C02 {
+                            let lo = cpu.peek(address);
+                            let hi = cpu.peek(address & 0xFF00);
                             (hi as u16) << 8 | (lo as u16)
                         } else {
-                            cpu.mem_read_u16(address)
+                            cpu.peek_u16(address)
                         };
                         format!("(${:04x}) = {:04x}", address, jmp_address)
+                    } else if ops.code == 0x7c {
+                        // Code corresponding to JMP absolute indexed indirect (65C02)
+                        let jmp_address = cpu.peek_u16(address.wrapping_add(cpu.register_x as u16));
+                        format!("(${:04x},X) = {:04x}", address, jmp_address)
                     } else {
                         format!("${:04x}", address)
                     }
@@ -100,33 +298,33 @@ pub fn trace(cpu: &CPU) -> String {
                     "${:04x},Y @ {:04x} = {:02x}",
                     address, mem_addr, stored_value
                 ),
+                AddressingMode::ZeroPage_Relative => {
+                    let target = begin.wrapping_add(3).wrapping_add((address_hi as i8) as u16);
+                    format!("${:02x} = {:02x},${:04x}", address_lo, stored_value, target)
+                },
                 _ => panic!("Unexpected addressing mode {:?} has ops-len 3. code {:02x}", ops.mode, ops.code),
             }
         },
         _ => String::from("")
     };
 
-    let hex_str = hex_dump
-        .iter()
-        .map(|z| format!("{:02x}", z))
-        .collect::<Vec<String>>()
-        .join(" ");
-    let asm_str = format!("{:04x}  {:8} {: >4} {}", begin, hex_str, ops.mnemonic, tmp)
-        .trim()
-        .to_string();
-
-    format!(
-        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
-        asm_str, cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer,
-    )
-    .to_ascii_uppercase()
+    TracedInstruction {
+        pc: begin,
+        bytes: hex_dump,
+        mnemonic: ops.mnemonic,
+        operand: tmp.trim_end().to_ascii_uppercase()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::bus::Bus;
+    use crate::cpu::CpuFlags;
+    use crate::cartridge::Rom;
     use crate::cartridge::test::test_rom;
+    use crate::ram::Ram;
+    use crate::single_step::LoggedRam;
 
     #[test]
     fn test_format_trace() {
@@ -138,6 +336,8 @@ mod test {
         bus.mem_write(104, 0x00);
 
         let mut cpu = CPU::new(bus);
+
+        cpu.halt_opcode = Some(0x00);
         cpu.program_counter = 0x64;
         cpu.register_a = 1;
         cpu.register_x = 2;
@@ -145,17 +345,17 @@ mod test {
         let mut result: Vec<String> = vec![];
         cpu.run_with_callback(|cpu| {
             result.push(trace(cpu));
-        });
+        }).unwrap();
         assert_eq!(
-            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD",
+            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD PPU:  0,  0 CYC:0",
             result[0]
         );
         assert_eq!(
-            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD",
+            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD PPU:  0,  6 CYC:2",
             result[1]
         );
         assert_eq!(
-            "0067  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD",
+            "0067  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD PPU:  0, 12 CYC:4",
             result[2]
         );
     }
@@ -175,15 +375,212 @@ mod test {
         bus.mem_write(0x400, 0xAA);
 
         let mut cpu = CPU::new(bus);
+
+        cpu.halt_opcode = Some(0x00);
         cpu.program_counter = 0x64;
         cpu.register_y = 0;
         let mut result: Vec<String> = vec![];
         cpu.run_with_callback(|cpu| {
             result.push(trace(cpu));
-        });
+        }).unwrap();
         assert_eq!(
-            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD",
+            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0",
             result[0]
         );
     }
+
+    #[test]
+    fn test_format_follows_cpu_model() {
+        // LDA ($20); BBR7 $21,$0200; BRK
+        let mut ram = Ram::new();
+        ram.load(0x0200, &[0xb2, 0x20, 0x7f, 0x21, 0xfb, 0x00]);
+        ram.load(0x0020, &[0x00, 0x03]);
+        ram.mem_write(0x0300, 0x5a);
+
+        let mut cpu = CPU::with_model(ram, CpuModel::Wdc65C02);
+        cpu.halt_opcode = Some(0x00);
+        cpu.program_counter = 0x0200;
+        assert_eq!(
+            "0200  B2 20     LDA ($20) = 0300 = 5A           A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0",
+            trace(&cpu)
+        );
+        cpu.step().unwrap();
+        assert_eq!(
+            "0202  7F 21 FB BBR7 $21 = 03,$0200              A:5A X:00 Y:00 P:24 SP:FD PPU:  0, 15 CYC:5",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_trace_does_not_access_the_bus() {
+        // LDA ($20),Y
+        let mut ram = LoggedRam::new();
+        ram.mem_write(0x0200, 0xb1);
+        ram.mem_write(0x0201, 0x20);
+        ram.mem_write(0x0020, 0x00);
+        ram.mem_write(0x0021, 0x03);
+        ram.take_log();
+
+        let mut cpu = CPU::new(ram);
+        cpu.program_counter = 0x0200;
+        assert_eq!(
+            "0200  B1 20     LDA ($20),Y = 0300 @ 0300 = 00  A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0",
+            trace(&cpu)
+        );
+        assert!(cpu.bus.take_log().is_empty());
+    }
+
+    #[test]
+    fn test_short_format() {
+        let mut cpu = CPU::new(Ram::new());
+        cpu.program_counter = 0x0000;
+        cpu.cycles = 29781;
+        assert_eq!(
+            "0000  00        BRK                             A:00 X:00 Y:00 P:24 SP:FD",
+            trace_short(&cpu)
+        );
+        // The position wraps at the end of a frame
+        assert_eq!(
+            "0000  00        BRK                             A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  1 CYC:29781",
+            trace(&cpu)
+        );
+    }
+
+    // Memory with a PPU stopped at a fixed position
+    struct PpuAt {
+        ram: Ram,
+        position: (u16, u16)
+    }
+
+    impl Mem for PpuAt {
+        fn peek(&self, address: u16) -> u8 {
+            self.ram.peek(address)
+        }
+
+        fn mem_write(&mut self, address: u16, data: u8) {
+            self.ram.mem_write(address, data);
+        }
+
+        fn ppu_position(&self) -> Option<(u16, u16)> {
+            Some(self.position)
+        }
+    }
+
+    #[test]
+    fn test_ppu_position_is_taken_from_the_bus() {
+        let mut cpu = CPU::new(PpuAt { ram: Ram::new(), position: (241, 3) });
+        cpu.program_counter = 0x0000;
+        cpu.cycles = 29781;
+        assert_eq!(
+            "0000  00        BRK                             A:00 X:00 Y:00 P:24 SP:FD PPU:241,  3 CYC:29781",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_trace_wraps_around_address_space() {
+        // LDA $1234 at 0xFFFF, with the operand at 0x0000, and BNE at 0xFFFE, which branches to 0x0002
+        let mut ram = Ram::new();
+        ram.load(0x0000, &[0x34, 0x12]);
+        ram.mem_write(0x1234, 0x56);
+        ram.mem_write(0xFFFF, 0xad);
+        let mut cpu = CPU::new(ram);
+        cpu.program_counter = 0xFFFF;
+        assert_eq!(
+            "FFFF  AD 34 12  LDA $1234 = 56                  A:00 X:00 Y:00 P:24 SP:FD",
+            trace_short(&cpu)
+        );
+
+        cpu.bus.load(0xFFFE, &[0xd0, 0x02]);
+        cpu.program_counter = 0xFFFE;
+        assert_eq!(
+            "FFFE  D0 02     BNE $0002                       A:00 X:00 Y:00 P:24 SP:FD",
+            trace_short(&cpu)
+        );
+    }
+
+    fn traced_cpu() -> CPU<Ram> {
+        // LDA $0300,X with the status NV-B-I-C
+        let mut ram = Ram::new();
+        ram.load(0x0200, &[0xbd, 0x00, 0x03]);
+        ram.mem_write(0x0301, 0x7f);
+        let mut cpu = CPU::new(ram);
+        cpu.program_counter = 0x0200;
+        cpu.register_x = 0x01;
+        cpu.status = CpuFlags::from_bits_truncate(0xF5);
+        cpu.cycles = 120;
+        cpu
+    }
+
+    #[test]
+    fn test_mesen_and_fceux_formats() {
+        let cpu = traced_cpu();
+        assert_eq!(
+            TraceFormat::Mesen.format(&cpu),
+            "0200  LDA $0300,X @ 0301 = 7F        A:00 X:01 Y:00 S:FD P:NV-BdIzC V:1   H:19  Cycle:120"
+        );
+        assert_eq!(
+            TraceFormat::Fceux.format(&cpu),
+            "A:00 X:01 Y:00 S:FD P:NVUBdIzC  $0200:BD 00 03  LDA $0300,X @ 0301 = 7F"
+        );
+    }
+
+    #[test]
+    fn test_template_format() {
+        let cpu = traced_cpu();
+        let template = Template::parse("{PC} [{bytes}] {DISASM} | a={A} x={X} y={Y} p={P} sp={SP} {FLAGS} {CYC}")
+            .unwrap();
+        assert_eq!(
+            TraceFormat::Template(template).format(&cpu),
+            "0200 [BD 00 03] LDA $0300,X @ 0301 = 7F | a=00 x=01 y=00 p=F5 sp=FD NV-BdIzC 120"
+        );
+        let template = Template::parse("{SCANLINE},{DOT}").unwrap();
+        assert_eq!(TraceFormat::Template(template).format(&cpu), "1,19");
+
+        assert_eq!(Template::parse("{PC} {Q}"), Err(String::from("Unknown placeholder {Q}")));
+        assert!(Template::parse("{PC").is_err());
+    }
+
+    /*
+        Run nestest in its automated mode, which starts at 0xC000 and needs no PPU, and compare every line of the
+        trace with the logs of Nintendulator, in the full and in the short format.
+    */
+    #[test]
+    fn test_nestest_golden_log() {
+        let read_log = |path| {
+            std::fs::read_to_string(path).unwrap()
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect::<Vec<String>>()
+        };
+        let expected = read_log("test_roms/nestest.log");
+        let expected_short = read_log("test_roms/nestest_no_cycle.log");
+        assert_eq!(expected.len(), expected_short.len());
+
+        let rom = Rom::new(&std::fs::read("test_roms/nestest.nes").unwrap()).unwrap();
+        let mut cpu = CPU::new(Bus::new(rom));
+        cpu.reset();
+        cpu.program_counter = 0xC000;
+
+        let mut lines: Vec<String> = vec![];
+        for number in 0..expected.len() {
+            let line = trace(&cpu);
+            for (found, log) in [(&line, &expected), (&trace_short(&cpu), &expected_short)] {
+                if *found != log[number] {
+                    let before = lines[number.saturating_sub(5)..].join("\n");
+                    let after = log[number + 1..(number + 4).min(log.len())].join("\n");
+                    panic!(
+                        "Trace diverges at line {}, after:\n{}\nexpected: {}\n   found: {}\nexpected next:\n{}",
+                        number + 1, before, log[number], found, after
+                    );
+                }
+            }
+            lines.push(line);
+            cpu.step().unwrap();
+        }
+
+        // The test stores the number of the first failed test in 0x02 (official opcodes) and 0x03 (unofficial)
+        assert_eq!((cpu.peek(0x02), cpu.peek(0x03)), (0x00, 0x00));
+    }
 }
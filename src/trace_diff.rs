@@ -0,0 +1,221 @@
+use std::fmt;
+
+/*
+    Comparison of two traces in the format of trace::trace, which is also the format of the log of nestest:
+        C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    The traces are aligned by line, and the first line that differs is reported field by field.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Pc,
+    Bytes,
+    Disassembly,
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Ppu,
+    Cyc
+}
+
+const REGISTER_FIELDS: [(&str, Field); 7] = [
+    ("A:", Field::A),
+    ("X:", Field::X),
+    ("Y:", Field::Y),
+    ("P:", Field::P),
+    ("SP:", Field::Sp),
+    ("PPU:", Field::Ppu),
+    ("CYC:", Field::Cyc)
+];
+
+impl Field {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Pc => "PC",
+            Field::Bytes => "bytes",
+            Field::Disassembly => "disassembly",
+            Field::A => "A",
+            Field::X => "X",
+            Field::Y => "Y",
+            Field::P => "P",
+            Field::Sp => "SP",
+            Field::Ppu => "PPU",
+            Field::Cyc => "CYC"
+        }
+    }
+
+    /*
+        Field with the given name, in any case. The disassembly can also be given as "disasm".
+    */
+    pub fn from_name(name: &str) -> Option<Field> {
+        let all = [
+            Field::Pc, Field::Bytes, Field::Disassembly, Field::A, Field::X, Field::Y, Field::P, Field::Sp,
+            Field::Ppu, Field::Cyc
+        ];
+        if name.eq_ignore_ascii_case("disasm") {
+            return Some(Field::Disassembly);
+        }
+        all.iter().find(|field| field.name().eq_ignore_ascii_case(name)).copied()
+    }
+}
+
+/*
+    Fields of a line of the trace, in the order of the line. The PPU and CYC columns are optional.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceLine {
+    pub fields: Vec<(Field, String)>
+}
+
+impl TraceLine {
+    pub fn parse(line: &str) -> Result<TraceLine, String> {
+        let registers = line.rfind(" A:").ok_or(format!("Not a trace line: {}", line))?;
+        let (instruction, registers) = (&line[..registers], &line[registers..]);
+        if instruction.len() < 15 || !instruction.is_char_boundary(15) {
+            return Err(format!("Not a trace line: {}", line));
+        }
+
+        let mut fields = vec![
+            (Field::Pc, instruction[..4].to_string()),
+            (Field::Bytes, instruction[4..15].trim().to_string()),
+            (Field::Disassembly, instruction[15..].trim().to_string())
+        ];
+        // The values end at the next label. Spaces inside a value are dropped, so "PPU:  0, 21" is "0,21".
+        for token in registers.split_whitespace() {
+            match REGISTER_FIELDS.iter().find(|(label, _)| token.starts_with(label)) {
+                Some((label, field)) => fields.push((*field, token[label.len()..].to_string())),
+                None => match fields.last_mut() {
+                    Some((Field::Ppu, value)) => value.push_str(token),
+                    _ => return Err(format!("Unexpected {} in trace line: {}", token, line))
+                }
+            }
+        }
+        Ok(TraceLine { fields })
+    }
+
+    pub fn get(&self, field: Field) -> Option<&str> {
+        self.fields.iter().find(|(f, _)| *f == field).map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub line: usize,                          // number of the trace line, starting at 1
+    pub left: Option<String>,                 // None if the trace ended before the line
+    pub right: Option<String>,
+    pub fields: Vec<(Field, String, String)>  // fields that differ, with their values on the left and on the right
+}
+
+/*
+    First line where the traces differ. Other output mixed in the logs is skipped. The ignored fields, and the ones
+    missing from either line (like the PPU column in the short format), are not compared.
+*/
+pub fn first_mismatch(left: &str, right: &str, ignored: &[Field]) -> Option<Mismatch> {
+    let mut left_lines = trace_lines(left);
+    let mut right_lines = trace_lines(right);
+
+    let mut number = 0;
+    loop {
+        number += 1;
+        let ((left_line, left_fields), (right_line, right_fields)) = match (left_lines.next(), right_lines.next()) {
+            (None, None) => return None,
+            (Some(left_line), Some(right_line)) => (left_line, right_line),
+            (left_line, right_line) => return Some(Mismatch {
+                line: number,
+                left: left_line.map(|(line, _)| line.to_string()),
+                right: right_line.map(|(line, _)| line.to_string()),
+                fields: vec![]
+            })
+        };
+
+        let fields = left_fields.fields.iter()
+            .filter(|(field, _)| !ignored.contains(field))
+            .filter_map(|(field, left_value)| match right_fields.get(*field) {
+                Some(right_value) if right_value != left_value => {
+                    Some((*field, left_value.clone(), right_value.to_string()))
+                }
+                _ => None
+            })
+            .collect::<Vec<(Field, String, String)>>();
+
+        if !fields.is_empty() {
+            return Some(Mismatch {
+                line: number,
+                left: Some(left_line.to_string()),
+                right: Some(right_line.to_string()),
+                fields
+            });
+        }
+    }
+}
+
+fn trace_lines(log: &str) -> impl Iterator<Item = (&str, TraceLine)> {
+    log.lines().filter_map(|line| TraceLine::parse(line).ok().map(|fields| (line, fields)))
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "First difference at line {}:", self.line)?;
+        writeln!(f, "  left:  {}", self.left.as_deref().unwrap_or("(end of trace)"))?;
+        writeln!(f, "  right: {}", self.right.as_deref().unwrap_or("(end of trace)"))?;
+        for (field, left, right) in self.fields.iter() {
+            writeln!(f, "  {:<12} {} != {}", field.name(), left, right)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LINE: &str = "C72A  D0 E0     BNE $C70C                       A:00 X:00 Y:00 P:26 SP:FB PPU:  3,113 CYC:409";
+
+    #[test]
+    fn test_parse_trace_line() {
+        let line = TraceLine::parse(LINE).unwrap();
+        assert_eq!(line.get(Field::Pc), Some("C72A"));
+        assert_eq!(line.get(Field::Bytes), Some("D0 E0"));
+        assert_eq!(line.get(Field::Disassembly), Some("BNE $C70C"));
+        assert_eq!(line.get(Field::P), Some("26"));
+        assert_eq!(line.get(Field::Ppu), Some("3,113"));
+        assert_eq!(line.get(Field::Cyc), Some("409"));
+
+        // Unofficial opcodes, and the short format without the PPU column
+        let line = TraceLine::parse("C6BD  04 A9    *NOP $A9 = 00                    A:AA X:97 Y:4E P:EF SP:F5 CYC:0")
+            .unwrap();
+        assert_eq!(line.get(Field::Disassembly), Some("*NOP $A9 = 00"));
+        assert_eq!(line.get(Field::Ppu), None);
+        assert!(TraceLine::parse("Ignoring memory write access at 16405").is_err());
+    }
+
+    #[test]
+    fn test_first_mismatch_by_field() {
+        let right = LINE.replace("P:26", "P:A4").replace("CYC:409", "CYC:410");
+        let left = format!("{}\n{}", LINE, LINE);
+        let right = format!("{}\n{}", LINE, right);
+
+        let mismatch = first_mismatch(&left, &right, &[]).unwrap();
+        assert_eq!(mismatch.line, 2);
+        assert_eq!(mismatch.fields, vec![
+            (Field::P, String::from("26"), String::from("A4")),
+            (Field::Cyc, String::from("409"), String::from("410")),
+        ]);
+
+        let mismatch = first_mismatch(&left, &right, &[Field::Cyc]).unwrap();
+        assert_eq!(mismatch.fields, vec![(Field::P, String::from("26"), String::from("A4"))]);
+        assert_eq!(first_mismatch(&left, &right, &[Field::Cyc, Field::P]), None);
+    }
+
+    #[test]
+    fn test_trace_ends_before_the_other() {
+        // Lines that are not part of the trace are skipped
+        let left = format!("{}\nIgnoring memory write access at 16405", LINE);
+        let mismatch = first_mismatch(&left, &format!("{}\n{}\n", LINE, LINE), &[]).unwrap();
+        assert_eq!(mismatch.line, 2);
+        assert_eq!(mismatch.left, None);
+        assert!(mismatch.to_string().contains("left:  (end of trace)"));
+    }
+}
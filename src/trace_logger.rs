@@ -0,0 +1,254 @@
+use crate::cpu::CpuError;
+use crate::cpu::Mem;
+use crate::cpu::CPU;
+use crate::opcodes;
+use crate::opcodes::Category;
+use crate::trace::TraceFormat;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/*
+    Logger of the trace of a running program. The lines are written to a file (or any other writer), or, in the
+    ring buffer mode, only the last ones are kept in memory, and written when the program stops at a breakpoint or
+    with an error. Filters select the instructions that are logged.
+*/
+pub struct TraceLogger<W: Write> {
+    pub enabled: bool,
+    pub format: TraceFormat,
+    pub pc_range: Option<RangeInclusive<u16>>, // only the instructions at these addresses
+    pub bank: Option<usize>,                   // only the instructions in this bank of PRG-ROM
+    pub categories: Vec<Category>,             // only these kinds of instructions (all of them, if empty)
+    writer: W,
+    ring_buffer: Option<(VecDeque<String>, usize)> // last lines, and how many are kept
+}
+
+/*
+    Why TraceLogger::run stopped.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Halted,          // the halt opcode of the CPU was fetched
+    Breakpoint(u16), // the program counter reached a breakpoint
+    Error(CpuError)
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Halted => write!(f, "Halted"),
+            Stop::Breakpoint(pc) => write!(f, "Breakpoint at {:04X}", pc),
+            Stop::Error(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl<W: Write> TraceLogger<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Self {
+        TraceLogger {
+            enabled: true,
+            format,
+            pc_range: None,
+            bank: None,
+            categories: vec![],
+            writer,
+            ring_buffer: None
+        }
+    }
+
+    /*
+        Keep only the last lines in memory, until they are dumped.
+    */
+    pub fn with_ring_buffer(writer: W, format: TraceFormat, capacity: usize) -> Self {
+        let mut logger = TraceLogger::new(writer, format);
+        logger.ring_buffer = Some((VecDeque::with_capacity(capacity), capacity));
+        logger
+    }
+
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    /*
+        True if the instruction at the program counter passes all the filters.
+    */
+    pub fn selects<M: Mem>(&self, cpu: &CPU<M>) -> bool {
+        let pc = cpu.program_counter;
+        let category = opcodes::table(cpu.model())[cpu.peek(pc) as usize].category;
+
+        self.enabled
+            && self.pc_range.as_ref().is_none_or(|range| range.contains(&pc))
+            && self.bank.is_none_or(|bank| cpu.bus.prg_bank(pc) == Some(bank))
+            && (self.categories.is_empty() || self.categories.contains(&category))
+    }
+
+    /*
+        Log the instruction at the program counter, if it is selected. It is meant to be called before the
+        instruction runs, as in the callback of CPU::run_with_callback.
+    */
+    pub fn log<M: Mem>(&mut self, cpu: &CPU<M>) -> io::Result<()> {
+        if !self.selects(cpu) {
+            return Ok(());
+        }
+        let line = self.format.format(cpu);
+        match &mut self.ring_buffer {
+            Some((lines, capacity)) => {
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                if *capacity > 0 {
+                    lines.push_back(line);
+                }
+                Ok(())
+            }
+            None => writeln!(self.writer, "{}", line)
+        }
+    }
+
+    /*
+        Write the lines kept by the ring buffer, and empty it. The writer is flushed in both modes.
+    */
+    pub fn dump(&mut self) -> io::Result<()> {
+        if let Some((lines, _)) = &mut self.ring_buffer {
+            for line in lines.drain(..) {
+                writeln!(self.writer, "{}", line)?;
+            }
+        }
+        self.writer.flush()
+    }
+
+    /*
+        Run the CPU, logging each instruction, until it halts, fails, or reaches one of the breakpoints. The trace
+        is dumped when it stops.
+    */
+    pub fn run<M: Mem>(&mut self, cpu: &mut CPU<M>, breakpoints: &[u16]) -> io::Result<Stop> {
+        loop {
+            if breakpoints.contains(&cpu.program_counter) {
+                self.dump()?;
+                return Ok(Stop::Breakpoint(cpu.program_counter));
+            }
+
+            let mut logged = Ok(());
+            let result = cpu.step_with_callback(|cpu| logged = self.log(cpu));
+            logged?;
+
+            let stop = match result {
+                Ok(result) if result.halted => Stop::Halted,
+                Ok(_) => continue,
+                Err(error) => Stop::Error(error)
+            };
+            self.dump()?;
+            return Ok(stop);
+        }
+    }
+}
+
+/*
+    The lines kept by the ring buffer are also dumped when the logger is dropped. This includes a panic of the
+    emulator only when panics unwind: with panic = "abort", nothing is dropped and the lines are lost.
+*/
+impl<W: Write> Drop for TraceLogger<W> {
+    fn drop(&mut self) {
+        let _ = self.dump();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+    use crate::ram::Ram;
+    use crate::trace::Template;
+
+    fn logged_lines<W: Write + AsRef<[u8]>>(logger: &TraceLogger<W>) -> Vec<String> {
+        String::from_utf8(logger.writer().as_ref().to_vec()).unwrap().lines().map(String::from).collect()
+    }
+
+    fn program() -> CPU<Ram> {
+        let code = assemble("
+            .org $0200
+                LDX #$03
+            loop:
+                DEX
+                BNE loop
+                JSR subroutine
+                BRK
+            subroutine:
+                RTS
+        ").unwrap();
+        let mut ram = Ram::new();
+        ram.load(0x0200, &code);
+        let mut cpu = CPU::new(ram);
+        cpu.program_counter = 0x0200;
+        cpu.halt_opcode = Some(0x00);
+        cpu
+    }
+
+    #[test]
+    fn test_log_with_filters() {
+        let mut cpu = program();
+        let mut logger = TraceLogger::new(vec![], TraceFormat::Template(Template::parse("{DISASM}").unwrap()));
+        // The halt opcode is logged before the CPU stops
+        logger.categories = vec![Category::Branch, Category::Jump];
+        assert_eq!(logger.run(&mut cpu, &[]).unwrap(), Stop::Halted);
+        assert_eq!(logged_lines(&logger), vec![
+            "BNE $0202", "BNE $0202", "BNE $0202", "JSR $0209", "RTS", "BRK",
+        ]);
+
+        let mut cpu = program();
+        let mut logger = TraceLogger::new(vec![], TraceFormat::Template(Template::parse("{PC}").unwrap()));
+        logger.pc_range = Some(0x0205..=0x0208);
+        logger.run(&mut cpu, &[]).unwrap();
+        assert_eq!(logged_lines(&logger), vec!["0205", "0208"]);
+
+        // Nothing is logged while disabled
+        let mut cpu = program();
+        let mut logger = TraceLogger::new(vec![], TraceFormat::NintendulatorShort);
+        logger.enabled = false;
+        logger.run(&mut cpu, &[]).unwrap();
+        assert!(logged_lines(&logger).is_empty());
+    }
+
+    #[test]
+    fn test_filter_by_bank() {
+        // JMP $C000 from the first bank to the second one, which holds NOP ; BRK
+        let mut code = vec![0x4c, 0x00, 0xc0];
+        code.resize(0x4000, 0x00);
+        code.extend([0xea, 0x00]);
+        let mut cpu = CPU::new(Bus::new(test_rom(code)));
+        cpu.halt_opcode = Some(0x00);
+
+        let mut logger = TraceLogger::new(vec![], TraceFormat::Template(Template::parse("{PC}").unwrap()));
+        logger.bank = Some(1);
+        logger.run(&mut cpu, &[]).unwrap();
+        assert_eq!(logged_lines(&logger), vec!["C000", "C001"]);
+    }
+
+    #[test]
+    fn test_ring_buffer_is_dumped_at_breakpoint() {
+        let mut cpu = program();
+        let mut logger = TraceLogger::with_ring_buffer(
+            vec![], TraceFormat::Template(Template::parse("{DISASM}").unwrap()), 2
+        );
+        assert_eq!(logger.run(&mut cpu, &[0x0205]).unwrap(), Stop::Breakpoint(0x0205));
+        assert_eq!(logged_lines(&logger), vec!["DEX", "BNE $0202"]);
+    }
+
+    #[test]
+    fn test_ring_buffer_is_dumped_on_error() {
+        // DEX ; KIL
+        let mut ram = Ram::new();
+        ram.load(0x0200, &[0xca, 0x02]);
+        let mut cpu = CPU::new(ram);
+        cpu.program_counter = 0x0200;
+
+        let mut logger = TraceLogger::with_ring_buffer(vec![], TraceFormat::NintendulatorShort, 10);
+        let stop = logger.run(&mut cpu, &[]).unwrap();
+        assert_eq!(stop, Stop::Error(CpuError::Jammed { opcode: 0x02, pc: 0x0201 }));
+        assert_eq!(logged_lines(&logger).len(), 2);
+    }
+}